@@ -1,5 +1,6 @@
 (require 's)
 (require 'dash)
+(require 'json)
 
 (defun sepl--remove-comments (code)
   "Removes comments and newlines from code given in CODE. After execution, all lines will be concatenated to a single line."
@@ -8,16 +9,123 @@
                   (car (s-split ";" line)))
                 (s-lines code))))
 
+(defvar-local sepl--json-protocol nil
+  "Non-nil in REPL buffers talking the JSON protocol to SEPL.")
+
+(defvar-local sepl--last-request-id 0
+  "Id of the last request sent from this REPL buffer.")
+
+(defvar-local sepl--pending-output ""
+  "Output received from SEPL that is not a complete line yet.")
+
+(defvar-local sepl--reply-handlers nil
+  "Alist from request id to the function handling its reply, for requests not shown in the REPL buffer.")
+
+(defvar sepl-event-functions nil
+  "Functions called with each renderer event SEPL sends over the JSON protocol, parsed to an alist.
+The event name is in the `event' field, e.g, \"reload\" or \"compile-error\" with the full compile log in `log'.")
+
+(defun sepl--json-request (id code)
+  "The JSON protocol line asking SEPL to evaluate CODE, tagged with ID."
+  (concat (json-encode `((id . ,id) (code . ,code))) "\n"))
+
+(defun sepl--format-reply (reply)
+  "Text to show for the JSON protocol message REPLY, parsed to an alist."
+  (let ((value (alist-get 'value reply))
+        (error (alist-get 'error reply))
+        (message (alist-get 'message reply))
+        (stdout (alist-get 'stdout reply))
+        (event (alist-get 'event reply)))
+    (concat (or stdout "")
+            (cond (error (format "ERROR: %s\n" error))
+                  (value (format "%s\n" value))
+                  (message (format "%s\n" message))
+                  ((equal event "reload") "Shader reloaded\n")
+                  ((equal event "compile-error")
+                   (format "ERROR: Shader compilation failed:\n%s\n"
+                           (sepl--format-diagnostics (alist-get 'diagnostics reply))))
+                  (t "")))))
+
+(defun sepl--format-diagnostics (diagnostics)
+  "Formats the parsed DIAGNOSTICS of a compile-error event like GCC does, one per line."
+  (s-join "\n"
+          (-map (lambda (diagnostic)
+                  (let-alist diagnostic
+                    (concat .file ":"
+                            (if .line (format "%d:" .line) "")
+                            (if .column (format "%d:" .column) "")
+                            (format " %s: %s" .severity .message))))
+                diagnostics)))
+
+(defun sepl-show-diagnostics (event)
+  "Shows the diagnostics of a compile-error EVENT in a compilation buffer, to jump to the errors. Meant for `sepl-event-functions'."
+  (when (equal (alist-get 'event event) "compile-error")
+    (with-current-buffer (get-buffer-create "*SEPL diagnostics*")
+      (let ((inhibit-read-only t))
+        (erase-buffer)
+        (insert (sepl--format-diagnostics (alist-get 'diagnostics event)) "\n"))
+      (compilation-mode)
+      (display-buffer (current-buffer)))))
+
+(defun sepl--send-request (process code &optional handler)
+  "Sends CODE to be evaluated by SEPL over the JSON protocol. The reply is given to HANDLER, or shown in the REPL buffer if nil."
+  (with-current-buffer (process-buffer process)
+    (let ((id (setq sepl--last-request-id (1+ sepl--last-request-id))))
+      (when handler
+        (push (cons id handler) sepl--reply-handlers))
+      (process-send-string process (sepl--json-request id code)))))
+
+(defun sepl--json-preoutput-filter (output)
+  "Turns JSON protocol messages from SEPL into REPL buffer text. Replies with a handler are passed on to it instead."
+  (let* ((lines (s-lines (concat sepl--pending-output output)))
+         (text ""))
+    ;; the last one is the start of the next line, if any
+    (setq sepl--pending-output (car (last lines)))
+    (dolist (line (butlast lines))
+      (unless (s-blank? line)
+        (let* ((reply (json-read-from-string line))
+               (handler (alist-get (alist-get 'id reply) sepl--reply-handlers)))
+          (when (alist-get 'event reply)
+            (run-hook-with-args 'sepl-event-functions reply))
+          (if handler
+              (progn
+                (setq sepl--reply-handlers
+                      (assq-delete-all (alist-get 'id reply) sepl--reply-handlers))
+                (funcall handler reply))
+            (setq text (concat text (sepl--format-reply reply)))
+            (when (equal (alist-get 'status reply) "done")
+              (setq text (concat text "> ")))))))
+    text))
+
+(defun sepl--json-input-sender (process input)
+  "Sends INPUT typed in the REPL buffer as a JSON protocol request."
+  (sepl--send-request process input))
+
+(defun sepl--use-json-protocol (buffer)
+  "Sets up the REPL in BUFFER to talk the JSON protocol. SEPL doesn't write prompts then, so we make our own."
+  (with-current-buffer buffer
+    (setq sepl--json-protocol t)
+    (setq-local comint-input-sender #'sepl--json-input-sender)
+    ;; first prompt before the filter is added, as it is not JSON
+    (comint-output-filter (get-buffer-process buffer) "> ")
+    (add-hook 'comint-preoutput-filter-functions #'sepl--json-preoutput-filter nil t)))
+
 (defun sepl--eval (code)
   "Internal helper function to evaluate code and print result in minibuffer"
-  (when (boundp 'sepl-repl-process)
-    (let ((tmp-buf (get-buffer-create "*sepl-tmp-buf*")))
-      (comint-redirect-send-command-to-process code tmp-buf sepl-repl-process nil t)
-      (with-current-buffer tmp-buf
-        ;; hack to wait for output to be present in tmp buffer
-        (sleep-for 0.5)
-        (message "=> %s" (s-replace "\n" "\n   " (s-trim (buffer-string)))))
-      (kill-buffer tmp-buf))))
+  (cond ((not (boundp 'sepl-repl-process)) nil)
+        ;; replies are tagged with our request, so no need to guess when they arrive
+        ((buffer-local-value 'sepl--json-protocol (process-buffer sepl-repl-process))
+         (sepl--send-request sepl-repl-process code
+                             (lambda (reply)
+                               (message "=> %s" (s-replace "\n" "\n   " (s-trim (sepl--format-reply reply)))))))
+        (t
+         (let ((tmp-buf (get-buffer-create "*sepl-tmp-buf*")))
+           (comint-redirect-send-command-to-process code tmp-buf sepl-repl-process nil t)
+           (with-current-buffer tmp-buf
+             ;; hack to wait for output to be present in tmp buffer
+             (sleep-for 0.5)
+             (message "=> %s" (s-replace "\n" "\n   " (s-trim (buffer-string)))))
+           (kill-buffer tmp-buf)))))
 
 (defun sepl-eval-sexp ()
   (interactive)
@@ -66,22 +174,69 @@
   :group 'sepl
   :type 'string)
 
-(defun sepl-repl-connect ()
-  "Connects to an existing SEPL instance, and starts a REPL interface."
+(defcustom sepl-repl-host "localhost"
+  "Host of the SEPL REPL to connect to"
+  :group 'sepl
+  :type 'string)
+
+(defcustom sepl-repl-port 42069
+  "Port of the SEPL REPL to connect to"
+  :group 'sepl
+  :type 'integer)
+
+(defcustom sepl-repl-protocol 'text
+  "Protocol of the SEPL REPL to connect to, as given with --repl-protocol. SEPL instances started from Emacs always use json."
+  :group 'sepl
+  :type '(choice (const text) (const json)))
+
+(defun sepl--parse-repl-address (output)
+  "Finds the REPL address SEPL prints at startup in OUTPUT. Returns (host . port), or nil if not printed yet."
+  (when (string-match "^SEPL_REPL=tcp://\\(.+\\):\\([0-9]+\\)$" output)
+    (cons (s-chop-prefix "[" (s-chop-suffix "]" (match-string 1 output)))
+          (string-to-number (match-string 2 output)))))
+
+(defun sepl-repl-connect (&optional host port protocol)
+  "Connects to an existing SEPL instance, and starts a REPL interface. Uses `sepl-repl-host', `sepl-repl-port' and `sepl-repl-protocol' unless HOST, PORT and PROTOCOL are given."
   (interactive)
   (let ((buffer (get-buffer-create "*SEPL REPL*")))
     (with-current-buffer buffer
-      (apply 'make-comint-in-buffer "SEPL" buffer '("localhost" . 42069) nil '())
+      (apply 'make-comint-in-buffer "SEPL" buffer
+             (cons (or host sepl-repl-host) (or port sepl-repl-port)) nil '())
+      (sepl-repl-mode)
+      (when (eq (or protocol sepl-repl-protocol) 'json)
+        (sepl--use-json-protocol buffer))
+      (pop-to-buffer buffer))))
+
+(defun sepl-repl-connect-socket (path)
+  "Connects to a SEPL instance listening on the Unix socket at PATH, and starts a REPL interface."
+  (interactive "fSEPL socket: ")
+  (let ((buffer (get-buffer-create "*SEPL REPL*")))
+    (with-current-buffer buffer
       (sepl-repl-mode)
+      (let ((process (make-network-process :name "SEPL"
+                                           :buffer buffer
+                                           :family 'local
+                                           :service (expand-file-name path))))
+        (set-marker (process-mark process) (point-max))
+        (set-process-filter process 'comint-output-filter))
+      (when (eq sepl-repl-protocol 'json)
+        (sepl--use-json-protocol buffer))
       (pop-to-buffer buffer))))
 
 (defun sepl-repl-start ()
-  "Starts a new SEPL instance and starts a REPL interface."
+  "Starts a new SEPL instance on a free port and starts a REPL interface."
   (interactive)
-  (let ((glsl-file (buffer-file-name (current-buffer))))
-    (start-process "sepl" "*SEPL-STDOUT*" sepl-program-bin glsl-file)
-    ;; give process 2 seconds to start
-    (sleep-for 2)
-    (sepl-repl-connect)))
+  (let* ((glsl-file (buffer-file-name (current-buffer)))
+         (process (start-process "sepl" "*SEPL-STDOUT*" sepl-program-bin
+                                 "--repl-port" "0" "--repl-protocol" "json" glsl-file))
+         (address nil))
+    ;; wait up to 10 seconds for the process to tell where the REPL is
+    (with-timeout (10 (error "SEPL did not start a REPL"))
+      (while (not address)
+        (accept-process-output process 0.1)
+        (setq address (sepl--parse-repl-address
+                       (with-current-buffer (process-buffer process)
+                         (buffer-string))))))
+    (sepl-repl-connect (car address) (cdr address) 'json)))
 
 (provide 'sepl-mode)
@@ -13,3 +13,46 @@
 (ert-deftest remove-comments-multiple-lines-test ()
   (should (s-equals? "(+ 1 2) 2 (define (square x)   (* x x))"
                      (sepl--remove-comments "(+ 1 2)\n2\n(define (square x)\n  (* x x))"))))
+
+(ert-deftest parse-repl-address-test ()
+  (should (equal '("127.0.0.1" . 42069)
+                 (sepl--parse-repl-address "SEPL_REPL=tcp://127.0.0.1:42069\n")))
+  (should (equal '("::1" . 5000)
+                 (sepl--parse-repl-address "[WARNING] something\nSEPL_REPL=tcp://[::1]:5000\n")))
+  (should (null (sepl--parse-repl-address "[ERROR] Could not start the REPL"))))
+
+(ert-deftest json-request-test ()
+  (should (equal '((id . 3) (code . "(define x\n  2)"))
+                 (json-read-from-string (sepl--json-request 3 "(define x\n  2)")))))
+
+(ert-deftest format-reply-test ()
+  (should (s-equals? "3\n" (sepl--format-reply '((id . 1) (value . "3") (status . "done")))))
+  (should (s-equals? "ERROR: oops\n" (sepl--format-reply '((id . 1) (error . "oops") (status . "done")))))
+  (should (s-equals? "[WARNING] slow\n" (sepl--format-reply '((message . "[WARNING] slow")))))
+  (should (s-equals? "hi\n3\n" (sepl--format-reply '((id . 1) (value . "3") (stdout . "hi\n") (status . "done")))))
+  (should (s-equals? "tick" (sepl--format-reply '((stdout . "tick")))))
+  (should (s-equals? "ERROR: Shader compilation failed:\nplasma.frag:3:1: error: oops\nplasma.frag: warning: hmm\n"
+                     (sepl--format-reply
+                      (json-read-from-string
+                       "{\"event\": \"compile-error\", \"log\": \"\", \"diagnostics\": [
+                          {\"file\": \"plasma.frag\", \"line\": 3, \"column\": 1, \"severity\": \"error\", \"message\": \"oops\"},
+                          {\"file\": \"plasma.frag\", \"line\": null, \"column\": null, \"severity\": \"warning\", \"message\": \"hmm\"}]}")))))
+
+(ert-deftest event-functions-test ()
+  (with-temp-buffer
+    (let* ((events nil)
+           (sepl-event-functions (list (lambda (event) (push (alist-get 'event event) events)))))
+      (sepl--json-preoutput-filter "{\"event\": \"reload\"}\n")
+      (should (equal '("reload") events)))))
+
+(ert-deftest json-preoutput-filter-test ()
+  (with-temp-buffer
+    (let ((handled nil))
+      (setq sepl--reply-handlers (list (cons 2 (lambda (reply) (setq handled reply)))))
+      ;; messages may arrive in pieces
+      (should (s-equals? "" (sepl--json-preoutput-filter "{\"id\": 1, \"val")))
+      (should (s-equals? "3\n> " (sepl--json-preoutput-filter "ue\": \"3\", \"status\": \"done\"}\n")))
+      ;; replies with a handler don't show up in the buffer
+      (should (s-equals? "" (sepl--json-preoutput-filter "{\"id\": 2, \"value\": \"4\", \"status\": \"done\"}\n")))
+      (should (equal "4" (alist-get 'value handled)))
+      (should (null sepl--reply-handlers)))))
@@ -28,9 +28,10 @@
                       (lambda () (get-elapsed-time)))
 (delete-dynamic-uniform! "elapsed_time")
 
-(set-dynamic-uniform! "screen_width")
+(set-dynamic-uniform! "screen_width"
+                      (lambda () (* 1.0 (car (screen-size)))))
 
-DYNAMIC_UNIFORM_TABLE
+(dynamic-uniforms)
 
 
 
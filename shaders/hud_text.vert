@@ -0,0 +1,15 @@
+#version 330 core
+
+in vec2 position;
+
+out vec2 uv;
+
+// left, bottom, right and top of the text in normalized device coordinates
+uniform vec4 bounds;
+
+void main() {
+  vec2 corner = (position + 1.0) / 2.0;
+  gl_Position = vec4(mix(bounds.xy, bounds.zw, corner), 0.0, 1.0);
+  // invert v
+  uv = vec2(corner.x, 1.0 - corner.y);
+}
@@ -12,10 +12,7 @@ void main() {
     discard;
   }
 
-  // black border
-  if (font_color.a <= 0.97) {
-    color = vec4(0.0, 0.0, 0.0, 1.0);
-  } else {
-    color = font_color;
-  }
+  // dark border, blended smoothly to keep small text readable
+  color = vec4(font_color.rgb * smoothstep(0.2, 0.8, font_color.a),
+               smoothstep(0.0, 0.4, font_color.a));
 }
@@ -0,0 +1,90 @@
+//! Saving what the shader draws to image files, like screenshots.
+
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use glium::{Texture2d, texture::RawImage2d};
+use image::{ImageFormat, RgbaImage, imageops};
+
+/// Pixels of a texture as an image, top row first. Made fully opaque, as the window ignores alpha too.
+pub fn read_texture(texture: &Texture2d) -> RgbaImage {
+    let pixels: RawImage2d<u8> = texture.read();
+    let mut image = RgbaImage::from_raw(pixels.width, pixels.height, pixels.data.into_owned())
+        .expect("Texture data should fit its dimensions");
+    for pixel in image.pixels_mut() {
+        pixel.0[3] = 255;
+    }
+    // OpenGL starts at the bottom row
+    imageops::flip_vertical(&image)
+}
+
+/// Checks that images can be saved in the format of the file extension, before going through the trouble of capturing one.
+pub fn check_format(path: &Path) -> Result<(), String> {
+    ImageFormat::from_path(path)
+        .map(|_| ())
+        .map_err(|err| format!("Could not save {}: {}", path.display(), err))
+}
+
+/// Saves the image in the format of the file extension, in a background thread as encoding large images takes a while.
+/// `on_saved` is called from the thread with what was saved, or why it failed.
+pub fn save_in_background(
+    image: RgbaImage,
+    path: PathBuf,
+    on_saved: impl FnOnce(Result<String, String>) + Send + 'static,
+) {
+    thread::spawn(move || {
+        on_saved(
+            image
+                .save(&path)
+                .map(|()| format!("Saved {}", path.display()))
+                .map_err(|err| format!("Could not save {}: {}", path.display(), err)),
+        );
+    });
+}
+
+/// File for a screenshot taken with the keyboard shortcut, in the working directory. Named after the shader and the time,
+/// so earlier screenshots are kept.
+pub fn screenshot_path(shader_file: &str, time: SystemTime) -> PathBuf {
+    let name = Path::new(shader_file)
+        .file_stem()
+        .map_or("shader".into(), |stem| stem.to_string_lossy());
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    PathBuf::from(format!("{}-{}.png", name, millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::{check_format, screenshot_path};
+
+    #[test]
+    fn screenshot_path_test() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(
+            PathBuf::from("plasma-1700000000123.png"),
+            screenshot_path("example/plasma.frag", time)
+        );
+        assert_eq!(
+            PathBuf::from("shader-1700000000123.png"),
+            screenshot_path("", time)
+        );
+    }
+
+    #[test]
+    fn check_format_test() {
+        assert_eq!(Ok(()), check_format(&PathBuf::from("plasma.png")));
+        assert_eq!(Ok(()), check_format(&PathBuf::from("poster.JPG")));
+        assert!(check_format(&PathBuf::from("plasma.xyz")).is_err());
+        assert!(check_format(&PathBuf::from("plasma")).is_err());
+    }
+}
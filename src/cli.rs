@@ -0,0 +1,480 @@
+//! Command line argument parsing. Simple enough that we don't need any external crates for it.
+
+use std::{env, path::PathBuf, time::Duration};
+
+use crate::protocol::Protocol;
+
+pub const USAGE: &str = "Usage: shade-eval-print-loop [OPTIONS] <FRAGMENT_SHADER>
+       shade-eval-print-loop render [OPTIONS] --output <FILE> <FRAGMENT_SHADER>
+
+Options:
+  --update-interval <MS>  Milliseconds between each dynamic uniform update (default: 50)
+  --frame-sync            Update dynamic uniforms in sync with each rendered frame
+  --repl-host <HOST>      Address the REPL listens on (default: 127.0.0.1, env: SEPL_REPL_HOST)
+  --repl-port <PORT>      Port the REPL listens on, 0 for any free port (default: 42069, env: SEPL_REPL_PORT)
+  --repl-socket <PATH>    Listen on a Unix domain socket instead of TCP (env: SEPL_REPL_SOCKET)
+  --repl-protocol <NAME>  REPL protocol, text or json (default: text, env: SEPL_REPL_PROTOCOL)
+  --error-display <MODE>  How compile errors are shown, overlay or panel (default: overlay)
+  -h, --help              Print this help text";
+
+pub const RENDER_USAGE: &str =
+    "Usage: shade-eval-print-loop render [OPTIONS] --output <FILE> <FRAGMENT_SHADER>
+
+Renders a single image without opening a window or the REPL, e.g, for thumbnails in CI.
+
+Options:
+  -o, --output <FILE>     Image file to write, in the format of its extension
+  --script <FILE>         Scheme script setting up the uniforms, run before rendering
+  --size <WIDTHxHEIGHT>   Size of the image (default: 1280x720)
+  --time <SECONDS>        Time of the clock in the rendered frame (default: 0)
+  -h, --help              Print this help text";
+
+/// Size of the window, and of images rendered without one unless asked otherwise.
+pub const DEFAULT_SIZE: (u32, u32) = (1280, 720);
+
+pub const DEFAULT_REPL_HOST: &str = "127.0.0.1";
+pub const DEFAULT_REPL_PORT: u16 = 42069;
+
+/// How compile errors are shown in the window.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ErrorDisplay {
+    /// Over the whole window, hiding the shader
+    #[default]
+    Overlay,
+    /// In a panel on the side, keeping the last good shader visible
+    Panel,
+}
+
+impl ErrorDisplay {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "overlay" => Ok(ErrorDisplay::Overlay),
+            "panel" => Ok(ErrorDisplay::Panel),
+            _ => Err(format!(
+                "Unknown error display {}. Should be overlay or panel",
+                name
+            )),
+        }
+    }
+
+    /// The other one, for switching between them.
+    pub fn toggled(self) -> Self {
+        match self {
+            ErrorDisplay::Overlay => ErrorDisplay::Panel,
+            ErrorDisplay::Panel => ErrorDisplay::Overlay,
+        }
+    }
+}
+
+/// Why the arguments were not parsed. Asking for help is not a mistake, so it is kept apart from invalid input.
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    /// The usage text that was asked for
+    Help(&'static str),
+    /// User friendly error message
+    Invalid(String),
+}
+
+impl From<String> for ArgsError {
+    fn from(message: String) -> Self {
+        ArgsError::Invalid(message)
+    }
+}
+
+impl From<&str> for ArgsError {
+    fn from(message: &str) -> Self {
+        ArgsError::Invalid(message.to_string())
+    }
+}
+
+/// Options given to the program on the command line.
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub fragment_shader_file: String,
+    pub update_interval: Option<Duration>,
+    pub frame_sync: bool,
+    pub repl_host: String,
+    pub repl_port: u16,
+    pub repl_socket: Option<PathBuf>,
+    pub repl_protocol: Protocol,
+    pub error_display: ErrorDisplay,
+}
+
+impl Args {
+    /// Parses the arguments, excluding the program name. Returns a user friendly error message on invalid input.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ArgsError> {
+        Self::parse_with_env(args, |name| env::var(name).ok())
+    }
+
+    /// Same as `parse`, with environment variables looked up by `env`. Options given as arguments take precedence.
+    fn parse_with_env<I: IntoIterator<Item = String>>(
+        args: I,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ArgsError> {
+        let mut fragment_shader_file = None;
+        let mut update_interval = None;
+        let mut frame_sync = false;
+        let mut repl_host = env("SEPL_REPL_HOST");
+        let mut repl_port = env("SEPL_REPL_PORT");
+        let mut repl_socket = env("SEPL_REPL_SOCKET");
+        let mut repl_protocol = env("SEPL_REPL_PROTOCOL");
+        let mut error_display = ErrorDisplay::default();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Err(ArgsError::Help(USAGE)),
+                "--update-interval" => {
+                    let value = args
+                        .next()
+                        .ok_or("--update-interval requires a value in milliseconds")?;
+                    let millis = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|millis| *millis > 0)
+                        .ok_or_else(|| {
+                            format!(
+                                "Invalid update interval {}. Should be a positive number of milliseconds",
+                                value
+                            )
+                        })?;
+                    update_interval = Some(Duration::from_millis(millis));
+                }
+                "--frame-sync" => frame_sync = true,
+                "--repl-host" => {
+                    repl_host = Some(args.next().ok_or("--repl-host requires an address")?);
+                }
+                "--repl-port" => {
+                    repl_port = Some(args.next().ok_or("--repl-port requires a port number")?);
+                }
+                "--repl-socket" => {
+                    repl_socket = Some(args.next().ok_or("--repl-socket requires a path")?);
+                }
+                "--repl-protocol" => {
+                    repl_protocol =
+                        Some(args.next().ok_or("--repl-protocol requires text or json")?);
+                }
+                "--error-display" => {
+                    error_display = ErrorDisplay::parse(
+                        &args
+                            .next()
+                            .ok_or("--error-display requires overlay or panel")?,
+                    )?;
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown option {}\n\n{}", flag, USAGE).into());
+                }
+                _ if fragment_shader_file.is_some() => {
+                    return Err(
+                        format!("Only one fragment shader is supported\n\n{}", USAGE).into(),
+                    );
+                }
+                _ => fragment_shader_file = Some(arg),
+            }
+        }
+
+        Ok(Args {
+            fragment_shader_file: fragment_shader_file
+                .ok_or_else(|| format!("A fragment shader file is required\n\n{}", USAGE))?,
+            update_interval,
+            frame_sync,
+            repl_host: repl_host.unwrap_or_else(|| DEFAULT_REPL_HOST.to_string()),
+            repl_port: match repl_port {
+                Some(port) => port.parse().map_err(|_| {
+                    format!(
+                        "Invalid REPL port {}. Should be a number from 0 to 65535",
+                        port
+                    )
+                })?,
+                None => DEFAULT_REPL_PORT,
+            },
+            repl_socket: repl_socket.map(PathBuf::from),
+            repl_protocol: repl_protocol
+                .map(|name| Protocol::parse(&name))
+                .transpose()?
+                .unwrap_or_default(),
+            error_display,
+        })
+    }
+}
+
+/// Options given to the render subcommand, for rendering an image without a window. Still parsed where that isn't
+/// supported, so mistakes are reported before that.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(target_vendor = "apple", allow(dead_code))]
+pub struct RenderArgs {
+    pub fragment_shader_file: String,
+    pub output: PathBuf,
+    pub script: Option<PathBuf>,
+    pub size: (u32, u32),
+    pub time: f32,
+}
+
+impl RenderArgs {
+    /// Parses the arguments following the subcommand. Returns a user friendly error message on invalid input.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ArgsError> {
+        let mut fragment_shader_file = None;
+        let mut output = None;
+        let mut script = None;
+        let mut size = DEFAULT_SIZE;
+        let mut time = 0.0;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Err(ArgsError::Help(RENDER_USAGE)),
+                "-o" | "--output" => {
+                    output = Some(PathBuf::from(
+                        args.next().ok_or("--output requires a file")?,
+                    ));
+                }
+                "--script" => {
+                    script = Some(PathBuf::from(
+                        args.next().ok_or("--script requires a file")?,
+                    ));
+                }
+                "--size" => {
+                    let value = args.next().ok_or("--size requires a width and height")?;
+                    size = parse_size(&value).ok_or_else(|| {
+                        format!(
+                            "Invalid size {}. Should be a width and height in pixels, like 1280x720",
+                            value
+                        )
+                    })?;
+                }
+                "--time" => {
+                    let value = args.next().ok_or("--time requires a number of seconds")?;
+                    time = value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|time| time.is_finite())
+                        .ok_or_else(|| {
+                            format!("Invalid time {}. Should be a number of seconds", value)
+                        })?;
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown option {}\n\n{}", flag, RENDER_USAGE).into());
+                }
+                _ if fragment_shader_file.is_some() => {
+                    return Err(format!(
+                        "Only one fragment shader is supported\n\n{}",
+                        RENDER_USAGE
+                    )
+                    .into());
+                }
+                _ => fragment_shader_file = Some(arg),
+            }
+        }
+
+        Ok(RenderArgs {
+            fragment_shader_file: fragment_shader_file
+                .ok_or_else(|| format!("A fragment shader file is required\n\n{}", RENDER_USAGE))?,
+            output: output
+                .ok_or_else(|| format!("An output file is required\n\n{}", RENDER_USAGE))?,
+            script,
+            size,
+            time,
+        })
+    }
+}
+
+/// Width and height from e.g, "1280x720". Both must be at least one pixel.
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once(['x', 'X'])?;
+    let width = width.parse().ok().filter(|width| *width > 0)?;
+    let height = height.parse().ok().filter(|height| *height > 0)?;
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::{
+        Args, ArgsError, DEFAULT_REPL_HOST, DEFAULT_REPL_PORT, DEFAULT_SIZE, ErrorDisplay,
+        RENDER_USAGE, RenderArgs, USAGE, parse_size,
+    };
+    use crate::protocol::Protocol;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        parse_with_env(args, &[])
+    }
+
+    fn parse_with_env(args: &[&str], env: &[(&str, &str)]) -> Result<Args, ArgsError> {
+        Args::parse_with_env(args.iter().map(|arg| arg.to_string()), |name| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn shader_file_test() {
+        assert_eq!(
+            Ok(Args {
+                fragment_shader_file: "plasma.frag".to_string(),
+                update_interval: None,
+                frame_sync: false,
+                repl_host: DEFAULT_REPL_HOST.to_string(),
+                repl_port: DEFAULT_REPL_PORT,
+                repl_socket: None,
+                repl_protocol: Protocol::Text,
+                error_display: ErrorDisplay::Overlay,
+            }),
+            parse(&["plasma.frag"])
+        );
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["plasma.frag", "hearts.frag"]).is_err());
+        assert!(parse(&["--not-a-flag", "plasma.frag"]).is_err());
+
+        // asking for help is not an error, even with a shader
+        assert_eq!(Err(ArgsError::Help(USAGE)), parse(&["--help"]));
+        assert_eq!(Err(ArgsError::Help(USAGE)), parse(&["plasma.frag", "-h"]));
+        assert!(matches!(parse(&[]), Err(ArgsError::Invalid(_))));
+    }
+
+    #[test]
+    fn update_interval_test() {
+        assert_eq!(
+            Some(Duration::from_millis(16)),
+            parse(&["--update-interval", "16", "plasma.frag"])
+                .unwrap()
+                .update_interval
+        );
+
+        assert!(parse(&["plasma.frag", "--update-interval"]).is_err());
+        assert!(parse(&["--update-interval", "0", "plasma.frag"]).is_err());
+        assert!(parse(&["--update-interval", "fast", "plasma.frag"]).is_err());
+    }
+
+    #[test]
+    fn frame_sync_test() {
+        assert!(!parse(&["plasma.frag"]).unwrap().frame_sync);
+        assert!(parse(&["plasma.frag", "--frame-sync"]).unwrap().frame_sync);
+    }
+
+    #[test]
+    fn repl_address_test() {
+        let args = parse(&["--repl-host", "0.0.0.0", "--repl-port", "0", "plasma.frag"]).unwrap();
+        assert_eq!("0.0.0.0", args.repl_host);
+        assert_eq!(0, args.repl_port);
+
+        let env = [("SEPL_REPL_HOST", "::1"), ("SEPL_REPL_PORT", "4242")];
+        let args = parse_with_env(&["plasma.frag"], &env).unwrap();
+        assert_eq!("::1", args.repl_host);
+        assert_eq!(4242, args.repl_port);
+
+        // arguments win over the environment
+        let args = parse_with_env(&["--repl-port", "1337", "plasma.frag"], &env).unwrap();
+        assert_eq!(1337, args.repl_port);
+
+        assert!(parse(&["--repl-port", "70000", "plasma.frag"]).is_err());
+        assert!(parse_with_env(&["plasma.frag"], &[("SEPL_REPL_PORT", "any")]).is_err());
+        assert!(parse(&["plasma.frag", "--repl-host"]).is_err());
+    }
+
+    #[test]
+    fn repl_socket_test() {
+        assert_eq!(
+            Some(PathBuf::from("/tmp/plasma.sock")),
+            parse(&["--repl-socket", "/tmp/plasma.sock", "plasma.frag"])
+                .unwrap()
+                .repl_socket
+        );
+        assert_eq!(
+            Some(PathBuf::from("/tmp/env.sock")),
+            parse_with_env(&["plasma.frag"], &[("SEPL_REPL_SOCKET", "/tmp/env.sock")])
+                .unwrap()
+                .repl_socket
+        );
+        assert!(parse(&["plasma.frag", "--repl-socket"]).is_err());
+    }
+
+    #[test]
+    fn repl_protocol_test() {
+        assert_eq!(
+            Protocol::Json,
+            parse(&["--repl-protocol", "json", "plasma.frag"])
+                .unwrap()
+                .repl_protocol
+        );
+        assert_eq!(
+            Protocol::Json,
+            parse_with_env(&["plasma.frag"], &[("SEPL_REPL_PROTOCOL", "json")])
+                .unwrap()
+                .repl_protocol
+        );
+        assert!(parse(&["--repl-protocol", "xml", "plasma.frag"]).is_err());
+    }
+
+    #[test]
+    fn error_display_test() {
+        assert_eq!(
+            ErrorDisplay::Panel,
+            parse(&["--error-display", "panel", "plasma.frag"])
+                .unwrap()
+                .error_display
+        );
+        assert!(parse(&["--error-display", "popup", "plasma.frag"]).is_err());
+        assert!(parse(&["plasma.frag", "--error-display"]).is_err());
+    }
+
+    fn parse_render(args: &[&str]) -> Result<RenderArgs, ArgsError> {
+        RenderArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn render_args_test() {
+        assert_eq!(
+            Ok(RenderArgs {
+                fragment_shader_file: "plasma.frag".to_string(),
+                output: PathBuf::from("plasma.png"),
+                script: None,
+                size: DEFAULT_SIZE,
+                time: 0.0,
+            }),
+            parse_render(&["plasma.frag", "-o", "plasma.png"])
+        );
+        assert_eq!(
+            Ok(RenderArgs {
+                fragment_shader_file: "plasma.frag".to_string(),
+                output: PathBuf::from("thumbs/plasma.jpg"),
+                script: Some(PathBuf::from("plasma.scm")),
+                size: (320, 180),
+                time: 2.5,
+            }),
+            parse_render(&[
+                "--script",
+                "plasma.scm",
+                "--size",
+                "320x180",
+                "--time",
+                "2.5",
+                "--output",
+                "thumbs/plasma.jpg",
+                "plasma.frag",
+            ])
+        );
+
+        assert!(parse_render(&["plasma.frag"]).is_err());
+        assert_eq!(
+            Err(ArgsError::Help(RENDER_USAGE)),
+            parse_render(&["--help"])
+        );
+        assert!(parse_render(&["-o", "plasma.png"]).is_err());
+        assert!(parse_render(&["plasma.frag", "-o", "plasma.png", "--time", "soon"]).is_err());
+        assert!(parse_render(&["plasma.frag", "-o", "plasma.png", "--size"]).is_err());
+        assert!(parse_render(&["plasma.frag", "-o", "plasma.png", "--repl-port", "0"]).is_err());
+    }
+
+    #[test]
+    fn parse_size_test() {
+        assert_eq!(Some((1920, 1080)), parse_size("1920x1080"));
+        assert_eq!(Some((64, 64)), parse_size("64X64"));
+        assert_eq!(None, parse_size("0x720"));
+        assert_eq!(None, parse_size("1280"));
+        assert_eq!(None, parse_size("1280x720x3"));
+        assert_eq!(None, parse_size("-1x720"));
+    }
+}
@@ -0,0 +1,128 @@
+//! The global animation clock. Follows wall time by default, but can be paused, scaled and moved around to inspect an animation.
+
+use std::time::Instant;
+
+/// Time in seconds used for everything animated.
+pub struct Clock {
+    /// Clock time at the moment of `anchor`
+    base: f64,
+    anchor: Instant,
+    scale: f64,
+    paused: bool,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    /// A running clock starting at 0.
+    pub fn new() -> Self {
+        Clock {
+            base: 0.0,
+            anchor: Instant::now(),
+            scale: 1.0,
+            paused: false,
+        }
+    }
+
+    /// Current time in seconds.
+    pub fn time(&self) -> f32 {
+        self.time_at(Instant::now()) as f32
+    }
+
+    fn time_at(&self, now: Instant) -> f64 {
+        if self.paused {
+            self.base
+        } else {
+            self.base + (now - self.anchor).as_secs_f64() * self.scale
+        }
+    }
+
+    /// Moves the anchor to now, so the next change only affects time from here on.
+    fn rebase(&mut self, now: Instant) {
+        self.base = self.time_at(now);
+        self.anchor = now;
+    }
+
+    pub fn pause(&mut self) {
+        self.rebase(Instant::now());
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.rebase(Instant::now());
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Jumps to the given time. Keeps running from there unless paused.
+    pub fn set_time(&mut self, time: f64) {
+        self.anchor = Instant::now();
+        self.base = time;
+    }
+
+    /// Moves the time forwards, or backwards with a negative number of seconds.
+    pub fn skip(&mut self, seconds: f64) {
+        self.rebase(Instant::now());
+        self.base += seconds;
+    }
+
+    /// Sets how fast time runs. 1.0 is real time, 0.5 half speed, and negative numbers run backwards.
+    pub fn set_scale(&mut self, scale: f64) {
+        self.rebase(Instant::now());
+        self.scale = scale;
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Clock;
+
+    #[test]
+    fn pause_test() {
+        let mut clock = Clock::new();
+        clock.set_time(3.0);
+        clock.pause();
+        let paused_at = clock.time();
+        assert!((3.0..3.1).contains(&paused_at));
+
+        // time stands still until resumed
+        assert_eq!(
+            paused_at,
+            clock.time_at(Instant::now() + Duration::from_secs(10)) as f32
+        );
+
+        clock.set_time(1.0);
+        clock.skip(-0.5);
+        assert!(clock.is_paused());
+        assert_eq!(0.5, clock.time());
+    }
+
+    #[test]
+    fn scale_test() {
+        let mut clock = Clock::new();
+        clock.pause();
+        clock.set_time(1.0);
+        clock.set_scale(2.0);
+        clock.resume();
+
+        let later = clock.anchor + Duration::from_secs(3);
+        assert_eq!(7.0, clock.time_at(later));
+
+        clock.set_scale(-1.0);
+        let later = clock.anchor + Duration::from_secs(3);
+        assert!(clock.time_at(later) < clock.base);
+    }
+}
@@ -1,9 +1,16 @@
 //! Command structures to standardize and simplify sending commands and instructions between threads.
 
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf, sync::mpsc::Sender};
+
 use image::RgbaImage;
 use nalgebra::Matrix4;
 
-#[derive(Debug, PartialEq)]
+use crate::{
+    diagnostics::Diagnostic, export::ExportSettings, stats::FrameStatsSummary,
+    uniform_check::DeclaredUniforms,
+};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum UniformValue {
     Float(f32),
     Vector3(f32, f32, f32),
@@ -13,13 +20,172 @@ pub enum UniformValue {
     // other texture types?
 }
 
+impl UniformValue {
+    /// The GLSL type the value is given to the shader as.
+    pub fn glsl_type(&self) -> &'static str {
+        match self {
+            UniformValue::Float(_) => "float",
+            UniformValue::Vector3(..) => "vec3",
+            UniformValue::Matrix(_) => "mat4",
+            UniformValue::RgbaTexture2D(_) => "sampler2D",
+        }
+    }
+}
+
+/// Short readable form of the value, for showing it on screen.
+impl Display for UniformValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UniformValue::Float(num) => write!(f, "{:.3}", num),
+            UniformValue::Vector3(x, y, z) => write!(f, "({:.3}, {:.3}, {:.3})", x, y, z),
+            UniformValue::Matrix(matrix) => {
+                let rows: Vec<String> = matrix
+                    .row_iter()
+                    .map(|row| {
+                        let elements: Vec<String> =
+                            row.iter().map(|elem| format!("{:.3}", elem)).collect();
+                        format!("({})", elements.join(" "))
+                    })
+                    .collect();
+                write!(f, "({})", rows.join(" "))
+            }
+            UniformValue::RgbaTexture2D(image) => {
+                write!(f, "{}x{} RGBA image", image.width(), image.height())
+            }
+        }
+    }
+}
+
 /// Commands meant to be received by the renderer.
 #[derive(Debug, PartialEq)]
 pub enum RenderCommand {
     SetUniform(String, UniformValue),
+    /// Uniforms for the given frame index, to be applied together before the frame is drawn
+    UniformBatch(u64, Vec<(String, UniformValue)>),
+    SetFrameSync(bool),
+    /// Name and last error of each failing dynamic uniform
+    SetUniformErrors(Vec<(String, String)>),
+    /// Adds or replaces the text element with the given id
+    DrawText(String, TextElement),
+    RemoveText(String),
+    ClearText,
+    ShowFrameStats(bool),
+    ShowUniformInspector(bool),
+    /// Asks for the current uniforms and textures
+    GetSnapshot(Reply<RenderSnapshot>),
+    /// Saves the shader to an image file, at the given width and height or the size of the window. Answered once the
+    /// image is captured
+    SaveScreenshot(PathBuf, Option<(u32, u32)>, Reply<Result<(), String>>),
+    /// Starts exporting frames. Answered when it has started, as the consumer has to send the uniforms of each frame
+    StartExport(ExportSettings, Reply<Result<(), String>>),
+}
+
+/// Channel a request to the renderer is answered on.
+#[derive(Debug)]
+pub struct Reply<T>(pub Sender<T>);
+
+/// Each request is answered on its own channel, so two are never the same. Lets commands be compared in tests.
+impl<T> PartialEq for Reply<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        false
+    }
+}
+
+impl<T> Reply<T> {
+    /// Answers the request. The one asking may have given up waiting, which is fine.
+    pub fn send(self, value: T) {
+        let _ = self.0.send(value);
+    }
+}
+
+/// The uniforms and textures of the renderer at the moment it was asked.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RenderSnapshot {
+    /// Values given to the shader, except textures
+    pub uniforms: BTreeMap<String, UniformValue>,
+    /// Width and height of each texture given to the shader
+    pub textures: BTreeMap<String, (u32, u32)>,
+    /// Active uniforms of the shader in use, with their GLSL types
+    pub shader_uniforms: DeclaredUniforms,
+}
+
+/// Largest font size of a text element, in pixels.
+pub const MAX_TEXT_SIZE: f32 = 512.0;
+
+/// Text drawn on top of the shader output until removed, e.g, parameter readouts or captions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextElement {
+    pub text: String,
+    /// Pixels from the left of the window to the left of the text
+    pub x: f32,
+    /// Pixels from the top of the window to the top of the text
+    pub y: f32,
+    /// Font size in pixels
+    pub size: f32,
+    pub color: (u8, u8, u8),
 }
 
 /// Commands the render engine sends to consumers (e.g, our Scheme instance)
 pub enum StateUpdateCommand {
     ScreenSizeChanged(u32, u32),
+    ShaderReloaded,
+    /// Full compile log, and the diagnostics parsed from it
+    ShaderCompileFailed(String, Vec<Diagnostic>),
+    KeyPressed(String),
+    KeyReleased(String),
+    MouseMoved(f32, f32),
+    MouseClicked(String),
+    /// Frame index and the time it took to draw it, in seconds
+    FrameRendered(u64, f32),
+    /// Sent before drawing a frame when frame synchronised, with the frame index. The consumer decides the frame time.
+    FrameTick(u64),
+    /// Keyboard shortcut for controlling the clock
+    TimeControl(TimeControl),
+    /// Performance of the recent frames, sent a few times per second
+    FrameStats(FrameStatsSummary),
+    /// Active uniforms of the shader in use, with their GLSL types. Sent whenever a shader compiles
+    ShaderUniforms(DeclaredUniforms),
+    /// Sent before rendering an exported frame, with the frame index, the index of the frame in the export and the time of
+    /// the frame. The consumer sends the uniforms of the frame back, like for a frame tick
+    ExportFrame(u64, u32, f32),
+    /// What was exported, or why it failed
+    ExportFinished(Result<String, String>),
+    /// What was saved, or why it failed. Screenshots are saved in the background, after the one asking was answered
+    ScreenshotSaved(Result<String, String>),
+}
+
+/// Changes to the global clock, e.g, from keyboard shortcuts in the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeControl {
+    TogglePause,
+    /// Seconds to move the time, negative to move back
+    Skip(f32),
+    /// Factor to multiply the time scale with
+    ScaleBy(f32),
+    Reset,
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+    use nalgebra::Matrix4;
+
+    use super::UniformValue;
+
+    #[test]
+    fn display_test() {
+        assert_eq!("1.250", UniformValue::Float(1.25).to_string());
+        assert_eq!(
+            "(1.000, 0.500, 0.000)",
+            UniformValue::Vector3(1.0, 0.5, 0.0).to_string()
+        );
+        assert_eq!(
+            "((1.000 0.000 0.000 0.000) (0.000 1.000 0.000 0.000) (0.000 0.000 1.000 0.000) (0.000 0.000 0.000 1.000))",
+            UniformValue::Matrix(Matrix4::identity()).to_string()
+        );
+
+        let texture = UniformValue::RgbaTexture2D(RgbaImage::new(64, 32));
+        assert_eq!("64x32 RGBA image", texture.to_string());
+        assert_eq!("sampler2D", texture.glsl_type());
+    }
 }
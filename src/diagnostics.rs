@@ -0,0 +1,322 @@
+//! Parsing of GLSL compile logs into diagnostics. Every driver has its own log format, so we try the ones we know about line by line.
+
+use std::fmt::Display;
+
+use serde_json::{Value, json};
+
+/// How bad a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    /// Parses the severity word of a log line, like "error", "WARNING" or "preprocessor error".
+    fn parse(word: &str) -> Option<Self> {
+        let word = word.to_lowercase();
+        if word.contains("error") {
+            Some(Severity::Error)
+        } else if word.contains("warning") {
+            Some(Severity::Warning)
+        } else if word.contains("info") || word.contains("note") {
+            Some(Severity::Info)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+/// A single problem reported by the shader compiler. Lines and columns start at 1, and are missing if the driver didn't tell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "file": self.file,
+            "line": self.line,
+            "column": self.column,
+            "severity": self.severity.to_string(),
+            "message": self.message,
+        })
+    }
+}
+
+/// Formatted like GCC does, which most editors know how to jump to.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        write!(f, " {}: {}", self.severity, self.message)
+    }
+}
+
+/// Parses the compile log of a shader read from `file`. Lines that aren't recognised are left out, unless nothing is recognised.
+/// Then the whole log becomes one diagnostic, so no errors get lost.
+pub fn parse_compile_log(log: &str, file: &str) -> Vec<Diagnostic> {
+    let diagnostics: Vec<Diagnostic> = log
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            parse_mesa_line(line)
+                .or_else(|| parse_nvidia_line(line))
+                .or_else(|| parse_khronos_line(line))
+        })
+        .map(|(line, column, severity, message)| Diagnostic {
+            file: file.to_string(),
+            line,
+            column,
+            severity,
+            message: message.trim().to_string(),
+        })
+        .collect();
+
+    if diagnostics.is_empty() && !log.trim().is_empty() {
+        vec![Diagnostic {
+            file: file.to_string(),
+            line: None,
+            column: None,
+            severity: Severity::Error,
+            message: log.trim().to_string(),
+        }]
+    } else {
+        diagnostics
+    }
+}
+
+/// Lines of `source` around the given line, with their line numbers. Lines outside of the source are left out.
+pub fn source_excerpt(source: &str, line: u32, context: u32) -> Vec<(u32, &str)> {
+    let first = line.saturating_sub(context).max(1);
+    source
+        .lines()
+        .zip(1..)
+        .skip(first as usize - 1)
+        .take_while(|(_, number)| *number <= line.saturating_add(context))
+        .map(|(text, number)| (number, text))
+        .collect()
+}
+
+/// Line, column, severity and message of a log line.
+type ParsedLine<'a> = (Option<u32>, Option<u32>, Severity, &'a str);
+
+/// Mesa, e.g, `0:12(3): error: syntax error, unexpected '}'`.
+fn parse_mesa_line(line: &str) -> Option<ParsedLine<'_>> {
+    let (_source, rest) = leading_number(line)?;
+    let (line, rest) = leading_number(rest.strip_prefix(':')?)?;
+    let (column, rest) = leading_number(rest.strip_prefix('(')?)?;
+    let (severity, message) = rest.strip_prefix("): ")?.split_once(": ")?;
+    Some((
+        Some(line),
+        Some(column),
+        Severity::parse(severity)?,
+        message,
+    ))
+}
+
+/// NVIDIA, e.g, `0(12) : error C0000: syntax error, unexpected '}'`. The source number is sometimes left out. Line 0 is
+/// used for problems that aren't on any line.
+fn parse_nvidia_line(line: &str) -> Option<ParsedLine<'_>> {
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    let (line, rest) = leading_number(rest.strip_prefix('(')?)?;
+    let (severity, message) = rest.strip_prefix(") : ")?.split_once(": ")?;
+    // the severity is followed by an error code
+    let severity = severity.split_whitespace().next()?;
+    let line = (line != 0).then_some(line);
+    Some((line, None, Severity::parse(severity)?, message))
+}
+
+/// AMD, Apple and the Khronos reference compiler, e.g, `ERROR: 0:12: '}' : syntax error`.
+fn parse_khronos_line(line: &str) -> Option<ParsedLine<'_>> {
+    let (severity, rest) = line.split_once(": ")?;
+    let severity = Severity::parse(severity)?;
+    let (_source, rest) = leading_number(rest)?;
+    let (line, rest) = leading_number(rest.strip_prefix(':')?)?;
+    Some((Some(line), None, severity, rest.strip_prefix(':')?))
+}
+
+/// Splits a number off the start of the text.
+fn leading_number(text: &str) -> Option<(u32, &str)> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Severity, parse_compile_log, source_excerpt};
+
+    fn diagnostic(
+        line: Option<u32>,
+        column: Option<u32>,
+        severity: Severity,
+        message: &str,
+    ) -> Diagnostic {
+        Diagnostic {
+            file: "plasma.frag".to_string(),
+            line,
+            column,
+            severity,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn mesa_test() {
+        let log = "0:12(3): error: syntax error, unexpected '}'\n0:4(7): warning: `x' used uninitialized\n";
+        assert_eq!(
+            vec![
+                diagnostic(
+                    Some(12),
+                    Some(3),
+                    Severity::Error,
+                    "syntax error, unexpected '}'"
+                ),
+                diagnostic(
+                    Some(4),
+                    Some(7),
+                    Severity::Warning,
+                    "`x' used uninitialized"
+                ),
+            ],
+            parse_compile_log(log, "plasma.frag")
+        );
+
+        assert_eq!(
+            vec![diagnostic(
+                Some(1),
+                Some(10),
+                Severity::Error,
+                "#version 500 is not supported"
+            )],
+            parse_compile_log(
+                "0:1(10): preprocessor error: #version 500 is not supported",
+                "plasma.frag"
+            )
+        );
+    }
+
+    #[test]
+    fn nvidia_test() {
+        let log = "0(12) : error C0000: syntax error, unexpected '}' at token \"}\"\n(0) : warning C7050: \"x\" might be used before being initialized";
+        assert_eq!(
+            vec![
+                diagnostic(
+                    Some(12),
+                    None,
+                    Severity::Error,
+                    "syntax error, unexpected '}' at token \"}\""
+                ),
+                diagnostic(
+                    None,
+                    None,
+                    Severity::Warning,
+                    "\"x\" might be used before being initialized"
+                ),
+            ],
+            parse_compile_log(log, "plasma.frag")
+        );
+    }
+
+    #[test]
+    fn amd_and_apple_test() {
+        // AMD and Intel on Windows prefix the log with a header, which is skipped
+        let log = "Fragment shader failed to compile with the following errors:\nERROR: 0:12: error(#132) Syntax error: \"}\" parse error\nERROR: error(#273) 1 compilation errors.  No code generated";
+        assert_eq!(
+            vec![diagnostic(
+                Some(12),
+                None,
+                Severity::Error,
+                "error(#132) Syntax error: \"}\" parse error"
+            )],
+            parse_compile_log(log, "plasma.frag")
+        );
+
+        let log = "ERROR: 0:5: Use of undeclared identifier 'colour'\nWARNING: 0:8: Overflow in implicit constant conversion";
+        assert_eq!(
+            vec![
+                diagnostic(
+                    Some(5),
+                    None,
+                    Severity::Error,
+                    "Use of undeclared identifier 'colour'"
+                ),
+                diagnostic(
+                    Some(8),
+                    None,
+                    Severity::Warning,
+                    "Overflow in implicit constant conversion"
+                ),
+            ],
+            parse_compile_log(log, "plasma.frag")
+        );
+    }
+
+    #[test]
+    fn unknown_format_test() {
+        assert_eq!(
+            vec![diagnostic(
+                None,
+                None,
+                Severity::Error,
+                "Something went wrong"
+            )],
+            parse_compile_log("Something went wrong\n", "plasma.frag")
+        );
+        assert!(parse_compile_log("", "plasma.frag").is_empty());
+    }
+
+    #[test]
+    fn source_excerpt_test() {
+        let source = "#version 330 core\nout vec4 color;\nvoid main() {\n  color = vec4(1.0)\n}\n";
+        assert_eq!(
+            vec![
+                (2, "out vec4 color;"),
+                (3, "void main() {"),
+                (4, "  color = vec4(1.0)"),
+                (5, "}")
+            ],
+            source_excerpt(source, 4, 2)
+        );
+        assert_eq!(
+            vec![(1, "#version 330 core"), (2, "out vec4 color;")],
+            source_excerpt(source, 1, 1)
+        );
+        assert!(source_excerpt(source, 40, 2).is_empty());
+    }
+
+    #[test]
+    fn display_test() {
+        assert_eq!(
+            "plasma.frag:12:3: error: syntax error",
+            diagnostic(Some(12), Some(3), Severity::Error, "syntax error").to_string()
+        );
+        assert_eq!(
+            "plasma.frag: error: oops",
+            diagnostic(None, None, Severity::Error, "oops").to_string()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Frame perfect export of the shader as a PNG sequence or an animated GIF. Frames are rendered at a fixed timestep, so
+//! the result is the same however fast the computer is.
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::mpsc::{Sender, channel},
+    thread,
+    time::Duration,
+};
+
+use image::{
+    Delay, Frame, RgbaImage,
+    codecs::gif::{GifEncoder, Repeat},
+};
+
+/// Frames per second an export can have. Anything outside of it makes no sense as an animation, and would break the
+/// frame delays of GIFs.
+pub const FPS_RANGE: RangeInclusive<f32> = 0.01..=1000.0;
+
+/// What to export, as asked for with `export-frames!`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportSettings {
+    /// Directory for a PNG sequence, or a file with the .gif extension for an animated GIF
+    pub path: PathBuf,
+    pub frames: u32,
+    pub fps: f32,
+    /// Width and height of the frames, or None for the size of the window
+    pub size: Option<(u32, u32)>,
+}
+
+impl ExportSettings {
+    /// Time of the clock in the given frame. Starts at 0, so an export is the same whenever it is made.
+    pub fn frame_time(&self, index: u32) -> f32 {
+        index as f32 / self.fps
+    }
+
+    pub fn is_gif(&self) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+    }
+}
+
+/// File of a frame in a PNG sequence. Numbered with as many digits as the last frame needs, so they sort in order.
+pub fn frame_path(dir: &Path, index: u32, frames: u32) -> PathBuf {
+    let digits = frames.saturating_sub(1).to_string().len();
+    dir.join(format!("frame-{:0digits$}.png", index))
+}
+
+enum Output {
+    Sequence(PathBuf),
+    Gif(Box<GifEncoder<BufWriter<File>>>),
+}
+
+/// Starts writing frames in a background thread, as encoding them is slow compared to rendering. Frames sent on the
+/// returned channel are written in order, and `on_finish` is called with the number written when all are. A frame that
+/// could not be rendered is sent as an error, which ends the export. Fails right away if the output can't be created.
+pub fn start_writer(
+    settings: &ExportSettings,
+    on_finish: impl FnOnce(Result<u32, String>) + Send + 'static,
+) -> Result<Sender<Result<RgbaImage, String>>, String> {
+    let delay = Duration::try_from_secs_f32(1.0 / settings.fps)
+        .map(Delay::from_saturating_duration)
+        .map_err(|err| format!("Invalid frames per second {}: {}", settings.fps, err))?;
+    let path = settings.path.clone();
+    let mut output = if settings.is_gif() {
+        let file = File::create(&path)
+            .map_err(|err| format!("Could not create {}: {}", path.display(), err))?;
+        let mut encoder = GifEncoder::new(BufWriter::new(file));
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|err| err.to_string())?;
+        Output::Gif(Box::new(encoder))
+    } else {
+        fs::create_dir_all(&path)
+            .map_err(|err| format!("Could not create {}: {}", path.display(), err))?;
+        Output::Sequence(path.clone())
+    };
+    let (frames, frames_receiver) = channel::<Result<RgbaImage, String>>();
+    let frame_count = settings.frames;
+
+    thread::spawn(move || {
+        let mut written = 0;
+        for frame in frames_receiver {
+            let result = frame.and_then(|image| match &mut output {
+                Output::Sequence(dir) => image
+                    .save(frame_path(dir, written, frame_count))
+                    .map_err(|err| err.to_string()),
+                Output::Gif(encoder) => encoder
+                    .encode_frame(Frame::from_parts(image, 0, 0, delay))
+                    .map_err(|err| err.to_string()),
+            });
+            if let Err(err) = result {
+                // the rest would be missing a frame, so no point in going on
+                on_finish(Err(format!(
+                    "Could not export frame {} to {}: {}",
+                    written,
+                    path.display(),
+                    err
+                )));
+                return;
+            }
+            written += 1;
+        }
+        // the encoder finishes the file when dropped
+        drop(output);
+        if written < frame_count {
+            on_finish(Err(format!(
+                "Stopped after {} of {} frames",
+                written, frame_count
+            )));
+        } else {
+            on_finish(Ok(written));
+        }
+    });
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        path::{Path, PathBuf},
+    };
+
+    use super::{ExportSettings, frame_path, start_writer};
+
+    #[test]
+    fn export_settings_test() {
+        let settings = ExportSettings {
+            path: PathBuf::from("out/demo.GIF"),
+            frames: 120,
+            fps: 30.0,
+            size: Some((640, 360)),
+        };
+        assert!(settings.is_gif());
+        assert_eq!(0.0, settings.frame_time(0));
+        assert_eq!(2.0, settings.frame_time(60));
+
+        assert!(
+            !ExportSettings {
+                path: PathBuf::from("out/frames"),
+                ..settings
+            }
+            .is_gif()
+        );
+    }
+
+    #[test]
+    fn frame_path_test() {
+        let dir = Path::new("out");
+        assert_eq!(PathBuf::from("out/frame-007.png"), frame_path(dir, 7, 120));
+        assert_eq!(PathBuf::from("out/frame-99.png"), frame_path(dir, 99, 100));
+        assert_eq!(PathBuf::from("out/frame-0.png"), frame_path(dir, 0, 1));
+    }
+
+    #[test]
+    fn invalid_fps_test() {
+        let path = env::temp_dir().join("sepl-invalid-fps-test");
+        for fps in [0.0, 1e-30] {
+            let settings = ExportSettings {
+                path: path.clone(),
+                frames: 10,
+                fps,
+                size: None,
+            };
+            assert!(start_writer(&settings, |_| {}).is_err(), "{}", fps);
+        }
+        // nothing is created for an export that can't be made
+        assert!(!path.exists());
+    }
+}
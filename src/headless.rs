@@ -0,0 +1,143 @@
+//! Rendering a single image without a window or the REPL, e.g, thumbnails in CI or on build servers. The OpenGL context
+//! is made without any surface through EGL, which also works with software rasterizers like Mesa's llvmpipe.
+
+use std::{collections::BTreeMap, fs, sync::mpsc::channel};
+
+use glium::{
+    backend::glutin::headless::Headless,
+    glutin::{
+        api::egl::{device::Device, display::Display as EglDisplay},
+        config::{ConfigSurfaceTypes, ConfigTemplateBuilder},
+        context::{ContextApi, ContextAttributesBuilder, GlProfile, NotCurrentGlContext, Version},
+        display::{Display, GlDisplay},
+    },
+};
+
+use crate::{
+    GLState, SEPLApp,
+    cli::RenderArgs,
+    command::{RenderCommand, UniformValue},
+    declared_uniforms,
+    diagnostics::parse_compile_log,
+    scheme::NetworkScheme,
+    uniform_check::{DeclaredUniforms, UniformProblem, check_uniform},
+};
+
+/// Renders the shader at the time and size asked for, with the uniforms the setup script gives it, and saves the image.
+pub fn render(args: &RenderArgs) -> Result<(), String> {
+    let facade = create_context()?;
+
+    let source = fs::read_to_string(&args.fragment_shader_file)
+        .map_err(|err| format!("Could not read {}: {}", args.fragment_shader_file, err))?;
+    let program = SEPLApp::create_program(&facade, &source).map_err(|log| {
+        parse_compile_log(&log, &args.fragment_shader_file)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+    let shader_uniforms = declared_uniforms(&program);
+    let mut state = GLState::new(&facade, program);
+
+    // the Scheme side runs on this thread without its main loop, so there is no REPL. The renderer events are never sent
+    let (render_sender, render_receiver) = channel();
+    let (_state_sender, state_receiver) = channel();
+    let mut scheme = NetworkScheme::new_env(state_receiver, render_sender);
+    let script = args
+        .script
+        .as_ref()
+        .map(|path| {
+            fs::read_to_string(path)
+                .map_err(|err| format!("Could not read {}: {}", path.display(), err))
+        })
+        .transpose()?;
+    scheme.run_single_frame(args.size, shader_uniforms.clone(), script, args.time)?;
+    drop(scheme);
+
+    let mut mismatches = BTreeMap::new();
+    for command in render_receiver.try_iter() {
+        let uniforms = match command {
+            RenderCommand::SetUniform(name, value) => vec![(name, value)],
+            RenderCommand::UniformBatch(_, uniforms) => uniforms,
+            // text and overlays are not part of the shader output
+            _ => continue,
+        };
+        for (name, value) in uniforms {
+            check_type(&name, &value, &shader_uniforms, &mut mismatches);
+            state.set_uniform(&facade, name, value);
+        }
+    }
+
+    let image = state.capture(&facade, args.size, &mismatches)?;
+    image
+        .save(&args.output)
+        .map_err(|err| format!("Could not save {}: {}", args.output.display(), err))?;
+    println!("[INFO] Saved {}", args.output.display());
+    Ok(())
+}
+
+/// Remembers the uniform as left out of the draw if its value is of the wrong type, like the window does.
+fn check_type(
+    name: &str,
+    value: &UniformValue,
+    shader_uniforms: &DeclaredUniforms,
+    mismatches: &mut BTreeMap<String, String>,
+) {
+    match check_uniform(name, value.glsl_type(), shader_uniforms) {
+        Some(problem @ UniformProblem::WrongType(..)) => {
+            let message = problem.message(name);
+            eprintln!("[WARNING] {}. Not used", message);
+            mismatches.insert(name.to_string(), message);
+        }
+        _ => {
+            mismatches.remove(name);
+        }
+    }
+}
+
+/// OpenGL context without any window or surface, on the first EGL device that can make one.
+fn create_context() -> Result<Headless, String> {
+    let devices = Device::query_devices()
+        .map_err(|err| format!("Could not find any EGL devices: {}", err))?;
+    let mut errors = Vec::new();
+    for device in devices {
+        match create_context_on(&device) {
+            Ok(context) => return Ok(context),
+            Err(err) => errors.push(err),
+        }
+    }
+    if errors.is_empty() {
+        errors.push("No EGL devices found".to_string());
+    }
+    Err(format!(
+        "Could not create an OpenGL context without a window: {}",
+        errors.join(", ")
+    ))
+}
+
+fn create_context_on(device: &Device) -> Result<Headless, String> {
+    // SAFETY: no native display is given, so EGL owns everything it creates for the device
+    let display = Display::Egl(
+        unsafe { EglDisplay::with_device(device, None) }.map_err(|err| err.to_string())?,
+    );
+    // no surface type, as nothing is drawn to one
+    let template = ConfigTemplateBuilder::new()
+        .with_surface_type(ConfigSurfaceTypes::empty())
+        .build();
+    // SAFETY: the template does not refer to any native window
+    let config = unsafe { display.find_configs(template) }
+        .map_err(|err| err.to_string())?
+        .next()
+        .ok_or("No suitable OpenGL config")?;
+
+    let attributes = ContextAttributesBuilder::new()
+        .with_context_api(ContextApi::OpenGl(Some(Version::new(3, 3))))
+        .with_profile(GlProfile::Core)
+        .build(None);
+    // SAFETY: the config comes from the same display
+    let context = unsafe { display.create_context(&config, &attributes) }
+        .map_err(|err| err.to_string())?
+        .make_current_surfaceless()
+        .map_err(|err| err.to_string())?;
+    Headless::new(context).map_err(|err| err.to_string())
+}
@@ -1,32 +1,63 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     env::args,
     fs,
-    path::Path,
+    path::{Path, PathBuf},
+    process,
     sync::mpsc::{Receiver, Sender, channel},
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use command::{RenderCommand, StateUpdateCommand};
+use cli::{Args, ArgsError, DEFAULT_SIZE, ErrorDisplay, RenderArgs};
+use command::{RenderCommand, RenderSnapshot, Reply, StateUpdateCommand, TimeControl};
+use diagnostics::{Diagnostic, Severity, parse_compile_log, source_excerpt};
+use export::ExportSettings;
 use geometry::{SQUARE, Vertex};
 use glium::{
-    Display, DrawParameters, Program,
+    Display, DrawError, DrawParameters, Program,
     ProgramCreationError::CompilationError,
-    Surface, Texture2d, VertexBuffer,
+    Rect, Surface, Texture2d, VertexBuffer,
     backend::{Facade, glutin::SimpleWindowBuilder},
+    draw_parameters::TimeElapsedQuery,
+    framebuffer::SimpleFrameBuffer,
     glutin::surface::WindowSurface,
     index::NoIndices,
-    uniforms::{AsUniformValue, DynamicUniforms, UniformValue},
-    winit::{application::ApplicationHandler, event_loop::EventLoop, window::Window},
+    texture::{MipmapsOption, UncompressedFloatFormat},
+    uniforms::{AsUniformValue, DynamicUniforms, UniformType, UniformValue},
+    winit::{
+        application::ApplicationHandler,
+        event::{ElementState, MouseScrollDelta},
+        event_loop::EventLoop,
+        keyboard::{Key, ModifiersState, NamedKey, PhysicalKey},
+        window::Window,
+    },
 };
+use image::RgbaImage;
 use notify::{Event, Watcher};
 use scheme::NetworkScheme;
-use text::TextRenderer;
+use shader_cache::ShaderCache;
+use stats::FrameStats;
+use text::{TextLine, TextRenderer};
+use uniform_check::{DeclaredUniforms, UniformProblem, check_uniform};
 
+mod capture;
+mod cli;
+mod clock;
 mod command;
+mod diagnostics;
+mod export;
 mod geometry;
+// made through EGL, which Apple platforms don't have
+#[cfg(not(target_vendor = "apple"))]
+mod headless;
+mod protocol;
 mod scheme;
+mod shader_cache;
+mod stats;
 mod text;
+mod timeline;
+mod uniform_check;
 
 const VERTEX_SHADER: &str = "#version 330 core
 
@@ -36,6 +67,28 @@ void main() {
   gl_Position = vec4(position, 0.0, 1.0);
 }";
 
+// how long to wait for the uniforms of a frame before drawing it anyway
+const FRAME_SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+
+// how long to wait for the uniforms of an exported frame before giving up on the export. An exported frame is never drawn
+// without them, so this can be generous
+const EXPORT_SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how often frame statistics and uniform values are shown, and statistics sent to consumers. More often would be unreadable
+const FRAME_STATS_INTERVAL: Duration = Duration::from_millis(250);
+
+// frames to wait for GPU timers before giving up on them
+const MAX_PENDING_GPU_TIMERS: usize = 8;
+
+// lines of source shown before and after the line of a compile error
+const EXCERPT_CONTEXT: u32 = 2;
+
+const ERROR_COLOR: (u8, u8, u8) = (255, 80, 80);
+const WARNING_COLOR: (u8, u8, u8) = (255, 200, 60);
+const INFO_COLOR: (u8, u8, u8) = (120, 180, 255);
+const SOURCE_COLOR: (u8, u8, u8) = (170, 170, 170);
+const ERROR_SOURCE_COLOR: (u8, u8, u8) = (255, 255, 255);
+
 const PLACEHOLDER_FRAGMENT_SHADER: &str = "#version 330 core
 
 out vec4 color;
@@ -45,31 +98,90 @@ void main() {
 }
 ";
 
+#[cfg(not(target_vendor = "apple"))]
+fn render(args: &RenderArgs) -> Result<(), String> {
+    headless::render(args)
+}
+
+#[cfg(target_vendor = "apple")]
+fn render(_args: &RenderArgs) -> Result<(), String> {
+    Err("Rendering without a window is not supported on this platform".to_string())
+}
+
+/// Prints the usage text when asked for it, or the error otherwise, and exits.
+fn exit_with(err: ArgsError) -> ! {
+    match err {
+        ArgsError::Help(usage) => {
+            println!("{}", usage);
+            process::exit(0);
+        }
+        ArgsError::Invalid(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    }
+}
+
 pub fn init() {
-    // TODO: make it more clear with error messages etc. that program requires an input file
-    let input_file = args()
-        .nth(1)
-        .expect("fragment shader filename should be the only argument!");
+    let mut args: Vec<String> = args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "render") {
+        let render_args = RenderArgs::parse(args.drain(1..)).unwrap_or_else(exit_with);
+        if let Err(err) = render(&render_args) {
+            eprintln!("[ERROR] {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let args = Args::parse(args).unwrap_or_else(exit_with);
 
     let event_loop = EventLoop::new().expect("Failed to create event loop");
-    let mut app = SEPLApp::new(&event_loop, input_file);
+    let mut app = SEPLApp::new(&event_loop, args.fragment_shader_file);
+    app.error_display = args.error_display;
 
     let (render_sender, render_receiver) = channel();
     let (sender, receiver) = channel();
+    let update_interval = args.update_interval;
+    let frame_sync = args.frame_sync;
+    let (repl_host, repl_port, repl_socket) = (args.repl_host, args.repl_port, args.repl_socket);
+    let repl_protocol = args.repl_protocol;
+    let socket_path = repl_socket.clone();
     thread::spawn(move || {
-        let scheme = NetworkScheme::new_env(receiver, render_sender);
+        let mut scheme = NetworkScheme::new_env(receiver, render_sender);
+        if let Some(interval) = update_interval {
+            scheme.set_update_interval(interval);
+        }
+        if frame_sync {
+            scheme.set_frame_sync(true);
+        }
+        scheme.set_repl_address(repl_host, repl_port);
+        scheme.set_repl_protocol(repl_protocol);
+        if let Some(path) = socket_path {
+            scheme.set_repl_socket(path);
+        }
         scheme.main_loop();
     });
 
     app.set_render_command_receiver(render_receiver);
     app.set_state_update_command_sender(sender);
     event_loop.run_app(&mut app).expect("Could not run app");
+
+    // the next SEPL on the same path would have to clean up after us otherwise
+    if let Some(path) = repl_socket {
+        let _ = fs::remove_file(path);
+    }
 }
 
 struct SEPLApp {
     display: Display<WindowSurface>,
     window: Window,
     input_file: String,
+    // source of the last compiled fragment shader, for showing where compile errors are
+    shader_source: String,
+    // last version of each shader that compiled, from earlier sessions too
+    shader_cache: ShaderCache,
+    // the shader failed at startup, and the last version that compiled is shown instead
+    showing_cached_shader: bool,
     // need reference to the watcher to keep the file event loop running
     #[allow(dead_code)]
     input_file_watcher: Box<dyn Watcher>,
@@ -85,7 +197,54 @@ struct SEPLApp {
     should_rerender: bool,
 
     text_renderer: TextRenderer,
+    error_display: ErrorDisplay,
     last_error: Option<String>,
+    // parsed from the compile log in last_error
+    diagnostics: Vec<Diagnostic>,
+    // name and error of failing dynamic uniforms, shown together with compile errors
+    uniform_errors: Vec<(String, String)>,
+    // active uniforms of the program with their types. None for the placeholder, which declares nothing the user wrote
+    declared_uniforms: Option<DeclaredUniforms>,
+    // uniforms given a value of another type than the program declares, with the problem. Left out when drawing, as the
+    // draw would fail
+    uniform_mismatches: BTreeMap<String, String>,
+    // why the last frame could not be drawn, if it couldn't
+    draw_error: Option<String>,
+
+    // number of frames drawn so far
+    frame_count: u64,
+    // when frame synchronised, all dynamic uniforms are requested from the consumer before drawing each frame
+    frame_sync: bool,
+    // held modifier keys, for the keyboard shortcuts
+    modifiers: ModifiersState,
+    // keys pressed as part of a shortcut, whose releases are not passed on either
+    shortcut_keys: HashSet<PhysicalKey>,
+
+    // performance of the recent frames, and whether the overlay showing it is visible
+    frame_stats: FrameStats,
+    show_frame_stats: bool,
+    frame_stats_published: Instant,
+    // whether the overlay listing the uniforms is visible
+    show_uniform_inspector: bool,
+    // text of the visible overlays, refreshed together with the frame statistics
+    info_text: String,
+    // GPU timers of each pass of drawn frames, until the GPU is done with them
+    pending_gpu_timers: VecDeque<Vec<(&'static str, TimeElapsedQuery)>>,
+
+    // latest cursor position not yet sent to consumers. Sent at most once per event loop iteration to not flood them
+    pending_cursor_position: Option<(f32, f32)>,
+
+    // export in progress, if any. Renders one exported frame before each frame in the window
+    export: Option<ActiveExport>,
+}
+
+struct ActiveExport {
+    settings: ExportSettings,
+    // width and height of the frames, fixed when the export starts
+    size: (u32, u32),
+    next_frame: u32,
+    // to the thread writing the frames. Dropping it finishes the export
+    frames: Sender<Result<RgbaImage, String>>,
 }
 
 struct GLState {
@@ -98,6 +257,117 @@ struct GLState {
     textures: HashMap<String, Texture2d>,
 }
 
+impl GLState {
+    fn new<F: Facade>(facade: &F, program: Program) -> Self {
+        Self {
+            vertex_buffer: VertexBuffer::new(facade, &SQUARE)
+                .expect("Could not create vertex buffer"),
+            index_buffer: NoIndices(glium::index::PrimitiveType::TriangleStrip),
+            program,
+            uniforms: HashMap::new(),
+            textures: HashMap::new(),
+        }
+    }
+
+    fn set_uniform<F: Facade>(
+        &mut self,
+        facade: &F,
+        name: String,
+        uniform_value: command::UniformValue,
+    ) {
+        match uniform_value {
+            // Special texture handling to only handle them one time
+            command::UniformValue::RgbaTexture2D(image) => {
+                // TODO: error handling!
+                let texture = Texture2d::with_format(
+                    facade,
+                    image
+                        .rows()
+                        .map(|row| {
+                            row.map(|&elem| (elem.0[0], elem.0[1], elem.0[2], elem.0[3]))
+                                .collect()
+                        })
+                        .collect::<Vec<Vec<(u8, u8, u8, u8)>>>(),
+                    glium::texture::UncompressedFloatFormat::U8U8U8U8,
+                    glium::texture::MipmapsOption::AutoGeneratedMipmaps,
+                )
+                .unwrap();
+
+                self.textures.insert(name, texture);
+            }
+            uniform_value => {
+                self.uniforms.insert(name, uniform_value);
+            }
+        }
+    }
+
+    /// Draws the shader with the current uniforms and textures over the whole surface. Uniforms in `mismatches` are left
+    /// out, as values of the wrong type would fail the whole draw.
+    fn draw<S: Surface>(
+        &self,
+        surface: &mut S,
+        mismatches: &BTreeMap<String, String>,
+        timer: Option<&TimeElapsedQuery>,
+    ) -> Result<(), DrawError> {
+        let mut uniforms = HashMap::new();
+        let mut dynamic_uniforms = DynamicUniforms::new();
+        for (name, value) in &self.uniforms {
+            if !mismatches.contains_key(name) {
+                dynamic_uniforms.add(name.as_str(), value);
+            }
+        }
+        // looping twice to avoid ownership issues. Need sampler due to dynamic uniform expecting a reference
+        for (name, texture) in &self.textures {
+            if mismatches.contains_key(name) {
+                continue;
+            }
+            let sampler = texture
+                .sampled()
+                .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+                .minify_filter(glium::uniforms::MinifySamplerFilter::Linear)
+                .wrap_function(glium::uniforms::SamplerWrapFunction::BorderClamp);
+            uniforms.insert(name, sampler);
+        }
+        for (name, val) in &uniforms {
+            dynamic_uniforms.add(name, val);
+        }
+
+        surface.draw(
+            &self.vertex_buffer,
+            self.index_buffer,
+            &self.program,
+            &dynamic_uniforms,
+            &DrawParameters {
+                time_elapsed_query: timer,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Draws the shader into a texture of the given size and reads back the pixels.
+    fn capture<F: Facade>(
+        &self,
+        facade: &F,
+        (width, height): (u32, u32),
+        mismatches: &BTreeMap<String, String>,
+    ) -> Result<RgbaImage, String> {
+        let texture = Texture2d::empty_with_format(
+            facade,
+            UncompressedFloatFormat::U8U8U8U8,
+            MipmapsOption::NoMipmap,
+            width,
+            height,
+        )
+        .map_err(|err| format!("Could not create a {}x{} image: {}", width, height, err))?;
+        let mut framebuffer =
+            SimpleFrameBuffer::new(facade, &texture).map_err(|err| err.to_string())?;
+        framebuffer.clear_color(0.0, 0.0, 0.0, 1.0);
+        self.draw(&mut framebuffer, mismatches, None)
+            .map_err(|err| format!("Could not draw the shader: {}", err))?;
+        Ok(capture::read_texture(&texture))
+    }
+}
+
 /// Convert internal uniform commands to a Glium uniform value
 impl AsUniformValue for command::UniformValue {
     fn as_uniform_value(&self) -> UniformValue<'_> {
@@ -116,14 +386,10 @@ impl AsUniformValue for command::UniformValue {
 impl SEPLApp {
     fn new(event_loop: &EventLoop<()>, fragment_shader_file: String) -> Self {
         let (window, display) = SimpleWindowBuilder::new()
-            .with_inner_size(1280, 720)
+            .with_inner_size(DEFAULT_SIZE.0, DEFAULT_SIZE.1)
             .with_title("Shade Eval Print Loop")
             .build(event_loop);
 
-        let vertex_buffer =
-            VertexBuffer::new(&display, &SQUARE).expect("Could not create vertex buffer");
-        let index_buffer = NoIndices(glium::index::PrimitiveType::TriangleStrip);
-
         // listen to changes on the input file
         let (sender, receiver) = channel();
         let mut input_file_watcher =
@@ -137,37 +403,76 @@ impl SEPLApp {
 
         let text_renderer = TextRenderer::new(&display);
 
-        // fallback initially to a placeholder if compilation error
-        let mut program = Self::create_program(&display, fragment_shader_file.as_str());
+        // fallback initially to the last version that compiled, or a placeholder, if compilation error
+        let shader_cache = ShaderCache::new();
+        let shader_source = read_shader(&fragment_shader_file);
+        let mut program = Self::create_program(&display, &shader_source);
         let mut last_error = None;
-        if let Err(err) = program {
-            last_error = Some(err);
+        let mut diagnostics = Vec::new();
+        let mut showing_cached_shader = false;
+        match program {
+            Ok(_) => remember_shader(&shader_cache, &fragment_shader_file, &shader_source),
+            Err(err) => {
+                diagnostics = parse_compile_log(&err, &fragment_shader_file);
+                last_error = Some(err);
 
-            program =
-                Program::from_source(&display, VERTEX_SHADER, PLACEHOLDER_FRAGMENT_SHADER, None)
-                    .map_err(|_| "placeholder".to_string());
+                program = match shader_cache
+                    .load(Path::new(&fragment_shader_file))
+                    .map(|source| Self::create_program(&display, &source))
+                {
+                    Some(Ok(program)) => {
+                        showing_cached_shader = true;
+                        Ok(program)
+                    }
+                    _ => Program::from_source(
+                        &display,
+                        VERTEX_SHADER,
+                        PLACEHOLDER_FRAGMENT_SHADER,
+                        None,
+                    )
+                    .map_err(|_| "placeholder".to_string()),
+                };
+            }
         }
 
+        let program = program.expect("If this fails, it will be the end of Europe as we know it");
+        let declared_uniforms =
+            (last_error.is_none() || showing_cached_shader).then(|| declared_uniforms(&program));
+
         Self {
             window,
             display,
             input_file: fragment_shader_file,
+            shader_source,
+            shader_cache,
+            showing_cached_shader,
             input_file_events: receiver,
             input_file_watcher: Box::new(input_file_watcher),
 
             render_commands: None,
             state_update_commands: None,
-            state: GLState {
-                vertex_buffer,
-                index_buffer,
-                program: program
-                    .expect("If this fails, it will be the end of Europe as we know it"),
-                uniforms: HashMap::new(),
-                textures: HashMap::new(),
-            },
+            state: GLState::new(&display, program),
             should_rerender: true,
             text_renderer,
+            error_display: ErrorDisplay::default(),
             last_error,
+            diagnostics,
+            uniform_errors: Vec::new(),
+            declared_uniforms,
+            uniform_mismatches: BTreeMap::new(),
+            draw_error: None,
+            frame_count: 0,
+            frame_sync: false,
+            modifiers: ModifiersState::empty(),
+            shortcut_keys: HashSet::new(),
+            frame_stats: FrameStats::default(),
+            show_frame_stats: false,
+            frame_stats_published: Instant::now(),
+            show_uniform_inspector: false,
+            info_text: String::new(),
+            pending_gpu_timers: VecDeque::new(),
+            pending_cursor_position: None,
+            export: None,
         }
     }
 
@@ -177,6 +482,24 @@ impl SEPLApp {
 
     fn set_state_update_command_sender(&mut self, sender: Sender<StateUpdateCommand>) {
         self.state_update_commands.replace(sender);
+
+        // the shader may have compiled, or failed, before anyone was listening
+        if let Some(uniforms) = &self.declared_uniforms {
+            self.send_state_update(StateUpdateCommand::ShaderUniforms(uniforms.clone()));
+        }
+        if let Some(err) = &self.last_error {
+            self.send_state_update(StateUpdateCommand::ShaderCompileFailed(
+                err.clone(),
+                self.diagnostics.clone(),
+            ));
+        }
+    }
+
+    /// Sends a state update to the consumer, if any. Dropped if the consumer is gone, so the window keeps running.
+    fn send_state_update(&self, command: StateUpdateCommand) {
+        if let Some(sender) = &self.state_update_commands {
+            let _ = sender.send(command);
+        }
     }
 
     /// Checks for file change notifications, and reloads the fragment shader if gets any. This might also change the error state of the program if the fragment shader contains any syntax errors.
@@ -188,16 +511,31 @@ impl SEPLApp {
             ..
         })) = self.input_file_events.try_recv()
         {
-            let program = Self::create_program(&self.display, &self.input_file);
+            self.shader_source = read_shader(&self.input_file);
+            let program = Self::create_program(&self.display, &self.shader_source);
             match program {
                 Ok(program) => {
                     self.last_error = None;
+                    self.diagnostics.clear();
+                    self.showing_cached_shader = false;
+                    let uniforms = declared_uniforms(&program);
+                    self.declared_uniforms = Some(uniforms.clone());
                     self.state.program = program;
+                    self.check_all_uniform_types();
+                    remember_shader(&self.shader_cache, &self.input_file, &self.shader_source);
                     println!("[INFO]Refreshed program");
+                    // before the reload, so consumers reacting to it know the new uniforms
+                    self.send_state_update(StateUpdateCommand::ShaderUniforms(uniforms));
+                    self.send_state_update(StateUpdateCommand::ShaderReloaded);
                 }
                 Err(err) => {
-                    self.last_error = Some(err.clone());
                     eprintln!("[ERROR] {}", err);
+                    self.diagnostics = parse_compile_log(&err, &self.input_file);
+                    self.last_error = Some(err.clone());
+                    self.send_state_update(StateUpdateCommand::ShaderCompileFailed(
+                        err,
+                        self.diagnostics.clone(),
+                    ));
                 }
             }
 
@@ -208,55 +546,434 @@ impl SEPLApp {
 
     /// Checks the input port for any incoming render commands in a non-blocking way. If there are no input port, it does nothing. Same for no commands available.
     fn process_incoming_render_commands(&mut self) {
-        if let Some(receiver) = &self.render_commands {
-            // process one at a time to avoid clogging render loop
-            if let Ok(command) = receiver.try_recv() {
-                match command {
-                    // Special texture handling to only handle them one time
-                    RenderCommand::SetUniform(
-                        name,
-                        command::UniformValue::RgbaTexture2D(image),
-                    ) => {
-                        // TODO: error handling!
-                        let texture = Texture2d::with_format(
-                            &self.display,
-                            image
-                                .rows()
-                                .map(|row| {
-                                    row.map(|&elem| (elem.0[0], elem.0[1], elem.0[2], elem.0[3]))
-                                        .collect()
-                                })
-                                .collect::<Vec<Vec<(u8, u8, u8, u8)>>>(),
-                            glium::texture::UncompressedFloatFormat::U8U8U8U8,
-                            glium::texture::MipmapsOption::AutoGeneratedMipmaps,
-                        )
-                        .unwrap();
-
-                        self.state.textures.insert(name, texture);
+        // all that are queued, as Scheme may be waiting for the answer to a request behind lots of uniform updates
+        let commands: Vec<RenderCommand> = self
+            .render_commands
+            .as_ref()
+            .map(|receiver| receiver.try_iter().collect())
+            .unwrap_or_default();
+        for command in commands {
+            self.apply_render_command(command);
+        }
+    }
+
+    fn apply_render_command(&mut self, command: RenderCommand) {
+        match command {
+            RenderCommand::SetUniform(name, uniform_value) => {
+                self.set_uniform(name, uniform_value);
+            }
+            RenderCommand::UniformBatch(_, uniforms) => {
+                for (name, uniform_value) in uniforms {
+                    self.set_uniform(name, uniform_value);
+                }
+            }
+            RenderCommand::SetFrameSync(enabled) => {
+                self.frame_sync = enabled;
+            }
+            RenderCommand::SetUniformErrors(errors) => {
+                self.uniform_errors = errors;
+            }
+            RenderCommand::DrawText(id, element) => {
+                self.text_renderer.set_hud_text(id, element);
+            }
+            RenderCommand::RemoveText(id) => {
+                self.text_renderer.remove_hud_text(&id);
+            }
+            RenderCommand::ClearText => {
+                self.text_renderer.clear_hud();
+            }
+            RenderCommand::ShowFrameStats(enabled) => {
+                self.show_frame_stats = enabled;
+                self.update_info_text();
+            }
+            RenderCommand::ShowUniformInspector(enabled) => {
+                self.show_uniform_inspector = enabled;
+                self.update_info_text();
+            }
+            RenderCommand::GetSnapshot(reply) => {
+                reply.send(self.snapshot());
+                return;
+            }
+            RenderCommand::SaveScreenshot(path, size, reply) => {
+                self.save_screenshot(path, size, Some(reply));
+                return;
+            }
+            RenderCommand::StartExport(settings, reply) => {
+                reply.send(self.start_export(settings));
+            }
+        }
+        self.should_rerender = true;
+    }
+
+    fn set_uniform(&mut self, name: String, uniform_value: command::UniformValue) {
+        self.check_uniform_type(&name, uniform_value.glsl_type());
+        self.state.set_uniform(&self.display, name, uniform_value);
+    }
+
+    /// Draws the shader offscreen at the given size, without any text on top, and reads back the pixels.
+    fn capture(&self, size: (u32, u32)) -> Result<RgbaImage, String> {
+        self.state
+            .capture(&self.display, size, &self.uniform_mismatches)
+    }
+
+    /// Captures the shader at the given size, or the size of the window, and saves it to the file. The one asking is
+    /// answered once it is captured, as encoding takes a while. How saving went is sent to the consumer, like for exports.
+    fn save_screenshot(
+        &self,
+        path: PathBuf,
+        size: Option<(u32, u32)>,
+        reply: Option<Reply<Result<(), String>>>,
+    ) {
+        let size = size.unwrap_or_else(|| self.display.get_framebuffer_dimensions());
+        match capture::check_format(&path).and_then(|()| self.capture(size)) {
+            Ok(image) => {
+                if let Some(reply) = reply {
+                    reply.send(Ok(()));
+                }
+                let state_update_commands = self.state_update_commands.clone();
+                capture::save_in_background(image, path, move |result| {
+                    if let Some(sender) = state_update_commands {
+                        let _ = sender.send(StateUpdateCommand::ScreenshotSaved(result));
                     }
-                    RenderCommand::SetUniform(name, uniform_value) => {
-                        self.state.uniforms.insert(name, uniform_value);
+                });
+            }
+            Err(err) => {
+                eprintln!("[ERROR] {}", err);
+                if let Some(reply) = reply {
+                    reply.send(Err(err));
+                }
+            }
+        }
+    }
+
+    fn start_export(&mut self, settings: ExportSettings) -> Result<(), String> {
+        if self.export.is_some() {
+            return Err("An export is already running".to_string());
+        }
+        let size = settings
+            .size
+            .unwrap_or_else(|| self.display.get_framebuffer_dimensions());
+
+        let state_update_commands = self.state_update_commands.clone();
+        let path = settings.path.clone();
+        let frames = export::start_writer(&settings, move |result| {
+            let result =
+                result.map(|written| format!("Exported {} frames to {}", written, path.display()));
+            if let Some(sender) = state_update_commands {
+                let _ = sender.send(StateUpdateCommand::ExportFinished(result));
+            }
+        })?;
+        println!(
+            "[INFO] Exporting {} frames of {}x{} to {}",
+            settings.frames,
+            size.0,
+            size.1,
+            settings.path.display()
+        );
+
+        self.export = Some(ActiveExport {
+            settings,
+            size,
+            next_frame: 0,
+            frames,
+        });
+        Ok(())
+    }
+
+    /// Renders the next frame of the export offscreen, with the uniforms the consumer sends for its time, and hands it to
+    /// the writer. Ends the export after the last frame, or if anything fails.
+    fn export_next_frame(&mut self) {
+        let Some(export) = &self.export else {
+            return;
+        };
+        let index = export.next_frame;
+        let size = export.size;
+        self.send_state_update(StateUpdateCommand::ExportFrame(
+            self.frame_count,
+            index,
+            export.settings.frame_time(index),
+        ));
+
+        let frame = if self.receive_frame_uniforms(EXPORT_SYNC_TIMEOUT) {
+            self.capture(size)
+        } else {
+            Err(format!("No uniforms received for frame {} in time", index))
+        };
+        // the export may have been replaced while waiting for the uniforms
+        let Some(export) = self.export.as_mut() else {
+            return;
+        };
+        let failed = frame.is_err();
+        // the writer stops at the first frame it can't write, and tells the consumer itself
+        let writer_stopped = export.frames.send(frame).is_err();
+        export.next_frame += 1;
+        if failed || writer_stopped || export.next_frame >= export.settings.frames {
+            self.export = None;
+        }
+    }
+
+    fn snapshot(&self) -> RenderSnapshot {
+        RenderSnapshot {
+            uniforms: self
+                .state
+                .uniforms
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            textures: self
+                .state
+                .textures
+                .iter()
+                .map(|(name, texture)| (name.clone(), texture.dimensions()))
+                .collect(),
+            shader_uniforms: declared_uniforms(&self.state.program),
+        }
+    }
+
+    /// Remembers whether a uniform value has the type the program declares it with. Unknown names are fine here, as the
+    /// compiler removes uniforms that don't affect the output.
+    fn check_uniform_type(&mut self, name: &str, glsl_type: &str) {
+        let problem = self
+            .declared_uniforms
+            .as_ref()
+            .and_then(|declared| check_uniform(name, glsl_type, declared));
+        match problem {
+            Some(problem @ UniformProblem::WrongType(..)) => {
+                let message = problem.message(name);
+                if self.uniform_mismatches.get(name) != Some(&message) {
+                    eprintln!("[WARNING] {}", message);
+                }
+                self.uniform_mismatches.insert(name.to_string(), message);
+            }
+            _ => {
+                self.uniform_mismatches.remove(name);
+            }
+        }
+    }
+
+    /// Checks the types of all uniform values again, for a new program.
+    fn check_all_uniform_types(&mut self) {
+        let types: Vec<(String, &'static str)> = self
+            .state
+            .uniforms
+            .iter()
+            .map(|(name, value)| (name.clone(), value.glsl_type()))
+            .chain(
+                self.state
+                    .textures
+                    .keys()
+                    .map(|name| (name.clone(), "sampler2D")),
+            )
+            .collect();
+
+        self.uniform_mismatches.clear();
+        for (name, glsl_type) in types {
+            self.check_uniform_type(&name, glsl_type);
+        }
+    }
+
+    /// Sends a frame tick to the consumer, and applies the dynamic uniforms it sends back for this frame.
+    /// Gives up after a while to not freeze the window if the consumer is busy.
+    fn sync_frame_uniforms(&mut self) {
+        self.send_state_update(StateUpdateCommand::FrameTick(self.frame_count));
+
+        if !self.receive_frame_uniforms(FRAME_SYNC_TIMEOUT) {
+            eprintln!(
+                "[WARNING] No uniforms received for frame {}. Drawing it anyway",
+                self.frame_count
+            );
+        }
+    }
+
+    /// Waits for the uniforms of the current frame, and applies them. Other render commands received while waiting are
+    /// applied in order. Gives false if they did not arrive in time.
+    fn receive_frame_uniforms(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while let Some(receiver) = &self.render_commands {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(RenderCommand::UniformBatch(frame, uniforms)) if frame == self.frame_count => {
+                    for (name, uniform_value) in uniforms {
+                        self.set_uniform(name, uniform_value);
                     }
+                    return true;
                 }
-                self.should_rerender = true;
+                Ok(command) => self.apply_render_command(command),
+                Err(_) => return false,
+            }
+        }
+        false
+    }
+
+    /// Records the GPU time of the frames the GPU is done with. Never waits for it, as that would stall the pipeline.
+    fn collect_gpu_timers(&mut self) {
+        while self
+            .pending_gpu_timers
+            .front()
+            .is_some_and(|timers| timers.iter().all(|(_, timer)| timer.is_ready()))
+        {
+            for (pass, timer) in self.pending_gpu_timers.pop_front().unwrap() {
+                self.frame_stats
+                    .record_gpu_time(pass, Duration::from_nanos(timer.get()));
+            }
+        }
+
+        // drivers not finishing them shouldn't make us pile them up
+        while self.pending_gpu_timers.len() > MAX_PENDING_GPU_TIMERS {
+            self.pending_gpu_timers.pop_front();
+        }
+    }
+
+    /// Updates the frame statistics and uniform overlays, and sends the statistics to the consumer, every now and then.
+    fn publish_frame_stats(&mut self) {
+        if self.frame_stats_published.elapsed() < FRAME_STATS_INTERVAL {
+            return;
+        }
+
+        self.frame_stats_published = Instant::now();
+        self.update_info_text();
+        self.send_state_update(StateUpdateCommand::FrameStats(self.frame_stats.summary()));
+    }
+
+    /// Refreshes the text of the visible frame statistics and uniform overlays.
+    fn update_info_text(&mut self) {
+        let mut sections = Vec::new();
+        if self.show_frame_stats {
+            sections.push(self.frame_stats.summary().to_text());
+        }
+        if self.show_uniform_inspector {
+            sections.push(self.uniform_inspector_text());
+        }
+        self.info_text = sections.join("\n\n");
+    }
+
+    /// Every uniform and texture given to the shader, with its type and value. Then the uniforms the shader declares
+    /// that were never given a value, as they are easy to miss.
+    fn uniform_inspector_text(&self) -> String {
+        let declared: HashMap<&String, &glium::program::Uniform> =
+            self.state.program.uniforms().collect();
+        // the compiler removes uniforms that don't affect the output, so these are not necessarily misspelled
+        let unused = |name: &String| {
+            if declared.contains_key(name) {
+                ""
+            } else {
+                " (not used by the shader)"
+            }
+        };
+
+        let mut lines = vec!["Uniforms".to_string()];
+        let mut uniforms: Vec<_> = self.state.uniforms.iter().collect();
+        uniforms.sort_by_key(|(name, _)| *name);
+        for (name, value) in uniforms {
+            lines.push(format!(
+                "{}: {} = {}{}",
+                name,
+                value.glsl_type(),
+                value,
+                unused(name)
+            ));
+        }
+        let mut textures: Vec<_> = self.state.textures.iter().collect();
+        textures.sort_by_key(|(name, _)| *name);
+        for (name, texture) in textures {
+            lines.push(format!(
+                "{}: sampler2D = {}x{} texture{}",
+                name,
+                texture.width(),
+                texture.height(),
+                unused(name)
+            ));
+        }
+        if lines.len() == 1 {
+            lines.push("none set".to_string());
+        }
+
+        let mut unset: Vec<_> = declared
+            .iter()
+            .filter(|(name, _)| {
+                !self.state.uniforms.contains_key(name.as_str())
+                    && !self.state.textures.contains_key(name.as_str())
+            })
+            .collect();
+        if !unset.is_empty() {
+            unset.sort_by_key(|(name, _)| *name);
+            lines.push("Declared but never set".to_string());
+            for (name, uniform) in unset {
+                lines.push(format!("{}: {}", name, glsl_type_name(uniform.ty)));
             }
         }
+        lines.join("\n")
     }
 
-    /// Read fragment shader from file, and create shader program combination. In our simplified scenario, the only reasonable error is a compilation error, so our error type is simply a String.
-    fn create_program<F: Facade>(display: &F, filename: &str) -> Result<Program, String> {
-        let fragment_shader =
-            fs::read_to_string(filename).expect("Could not read fragment shader!");
+    /// Lines for the error overlay, empty if there are no errors. Compile errors come first, as nothing works without a shader.
+    /// Each compile error is followed by the source lines around it, with the offending line highlighted.
+    fn error_lines(&self) -> Vec<TextLine> {
+        let mut lines = Vec::new();
+        if self.showing_cached_shader {
+            lines.push(TextLine::new(
+                format!(
+                    "Showing the last version of {} that compiled",
+                    self.input_file
+                ),
+                INFO_COLOR,
+            ));
+        }
+        for diagnostic in &self.diagnostics {
+            lines.push(TextLine::new(
+                diagnostic.to_string(),
+                severity_color(diagnostic.severity),
+            ));
 
-        Program::from_source(display, VERTEX_SHADER, fragment_shader.as_str(), None).map_err(
-            |err| {
-                if let CompilationError(compile_error, _) = err {
-                    compile_error
-                } else {
-                    "POSSIBLE DRIVER ISSUE!".to_string()
+            if let Some(line) = diagnostic.line {
+                let excerpt = source_excerpt(&self.shader_source, line, EXCERPT_CONTEXT);
+                let width = excerpt
+                    .last()
+                    .map_or(1, |(number, _)| number.to_string().len());
+                for (number, text) in excerpt {
+                    let (marker, color) = if number == line {
+                        ('>', ERROR_SOURCE_COLOR)
+                    } else {
+                        (' ', SOURCE_COLOR)
+                    };
+                    lines.push(TextLine::new(
+                        format!(
+                            "{} {:>width$} | {}",
+                            marker,
+                            number,
+                            text.replace('\t', "    ")
+                        ),
+                        color,
+                    ));
                 }
-            },
-        )
+                lines.push(TextLine::new("", SOURCE_COLOR));
+            }
+        }
+
+        lines.extend(self.uniform_errors.iter().map(|(name, err)| {
+            TextLine::new(format!("Dynamic uniform {}: {}", name, err), ERROR_COLOR)
+        }));
+        lines.extend(
+            self.uniform_mismatches
+                .values()
+                .map(|message| TextLine::new(format!("{}. Not used", message), WARNING_COLOR)),
+        );
+        if let Some(err) = &self.draw_error {
+            lines.push(TextLine::new(
+                format!("Could not draw the shader: {}", err),
+                ERROR_COLOR,
+            ));
+        }
+        lines
+    }
+
+    /// Create shader program combination from the fragment shader source. In our simplified scenario, the only reasonable error is a compilation error, so our error type is simply a String.
+    fn create_program<F: Facade>(display: &F, fragment_shader: &str) -> Result<Program, String> {
+        Program::from_source(display, VERTEX_SHADER, fragment_shader, None).map_err(|err| {
+            if let CompilationError(compile_error, _) = err {
+                compile_error
+            } else {
+                "POSSIBLE DRIVER ISSUE!".to_string()
+            }
+        })
     }
 }
 
@@ -280,60 +997,296 @@ impl ApplicationHandler for SEPLApp {
                 self.display.resize(new_size.into());
                 self.should_rerender = true;
 
-                if let Some(sender) = &self.state_update_commands {
-                    sender
-                        .send(StateUpdateCommand::ScreenSizeChanged(
-                            new_size.width,
-                            new_size.height,
-                        ))
-                        .unwrap();
+                self.send_state_update(StateUpdateCommand::ScreenSizeChanged(
+                    new_size.width,
+                    new_size.height,
+                ));
+            }
+            glium::winit::event::WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
+            glium::winit::event::WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed && self.modifiers.control_key() =>
+            {
+                // shortcuts are not passed on as key presses, so they don't trigger Scheme key handlers by accident
+                if is_shortcut(&event.logical_key) {
+                    self.shortcut_keys.insert(event.physical_key);
+                }
+                if is_character(&event.logical_key, "e") {
+                    self.error_display = self.error_display.toggled();
+                    self.should_rerender = true;
+                    return;
+                }
+                if is_character(&event.logical_key, "f") {
+                    self.show_frame_stats = !self.show_frame_stats;
+                    self.update_info_text();
+                    self.should_rerender = true;
+                    return;
+                }
+                if is_character(&event.logical_key, "u") {
+                    self.show_uniform_inspector = !self.show_uniform_inspector;
+                    self.update_info_text();
+                    self.should_rerender = true;
+                    return;
+                }
+                if is_character(&event.logical_key, "s") {
+                    // holding the keys down should not fill the disk
+                    if !event.repeat {
+                        let path = capture::screenshot_path(&self.input_file, SystemTime::now());
+                        self.save_screenshot(path, None, None);
+                    }
+                    return;
+                }
+                match time_shortcut(&event.logical_key) {
+                    Some(control) => {
+                        self.send_state_update(StateUpdateCommand::TimeControl(control))
+                    }
+                    None if !event.repeat => self.send_state_update(
+                        StateUpdateCommand::KeyPressed(key_name(&event.logical_key)),
+                    ),
+                    None => {}
                 }
             }
-            // only re-render if we have something to render. else, ignore event
-            glium::winit::event::WindowEvent::RedrawRequested if self.should_rerender => {
-                let mut uniforms = HashMap::new();
-                let mut dynamic_uniforms = DynamicUniforms::new();
-                for (name, value) in &self.state.uniforms {
-                    dynamic_uniforms.add(name.as_str(), value);
+            glium::winit::event::WindowEvent::KeyboardInput { event, .. } => {
+                if event.state == ElementState::Pressed
+                    && let Some(rows) = overlay_scroll_shortcut(&event.logical_key)
+                {
+                    self.text_renderer.scroll(rows);
+                    self.should_rerender = true;
+                }
+
+                // Scheme never saw the press of a shortcut
+                if event.state == ElementState::Released
+                    && self.shortcut_keys.remove(&event.physical_key)
+                {
+                    return;
                 }
-                // looping twice to avoid ownership issues. Need sampler due to dynamic uniform expecting a reference
-                for (name, texture) in &self.state.textures {
-                    let sampler = texture
-                        .sampled()
-                        .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
-                        .minify_filter(glium::uniforms::MinifySamplerFilter::Linear)
-                        .wrap_function(glium::uniforms::SamplerWrapFunction::BorderClamp);
-                    uniforms.insert(name, sampler);
+
+                let key = key_name(&event.logical_key);
+                match event.state {
+                    ElementState::Pressed if !event.repeat => {
+                        self.send_state_update(StateUpdateCommand::KeyPressed(key))
+                    }
+                    ElementState::Released => {
+                        self.send_state_update(StateUpdateCommand::KeyReleased(key))
+                    }
+                    _ => {}
                 }
-                for (name, val) in &uniforms {
-                    dynamic_uniforms.add(name, val);
+            }
+            glium::winit::event::WindowEvent::MouseWheel { delta, .. } => {
+                // scrolling down moves the text up
+                let rows = match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => -(lines * 3.0) as isize,
+                    MouseScrollDelta::PixelDelta(position) => -(position.y / 20.0) as isize,
+                };
+                self.text_renderer.scroll(rows);
+                self.should_rerender = true;
+            }
+            glium::winit::event::WindowEvent::CursorMoved { position, .. } => {
+                self.pending_cursor_position = Some((position.x as f32, position.y as f32));
+            }
+            glium::winit::event::WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button,
+                ..
+            } => {
+                self.send_state_update(StateUpdateCommand::MouseClicked(format!("{:?}", button)));
+            }
+            // only re-render if we have something to render. else, ignore event
+            // drawing continuously while showing frame statistics or uniforms, to keep them up to date
+            glium::winit::event::WindowEvent::RedrawRequested
+                if self.should_rerender
+                    || self.frame_sync
+                    || self.show_frame_stats
+                    || self.show_uniform_inspector
+                    || self.export.is_some() =>
+            {
+                let frame_start = Instant::now();
+                // the window shows the exported frame, so it needs no uniforms of its own
+                if self.export.is_some() {
+                    self.export_next_frame();
+                } else if self.frame_sync {
+                    self.sync_frame_uniforms();
                 }
+                // waiting for uniforms is not part of the work of drawing
+                let draw_start = Instant::now();
 
+                // timers are not supported by all drivers. We go without GPU times then
+                let shader_timer = TimeElapsedQuery::new(&self.display).ok();
                 let mut frame = self.display.draw();
-                frame
-                    .draw(
-                        &self.state.vertex_buffer,
-                        self.state.index_buffer,
-                        &self.state.program,
-                        &dynamic_uniforms,
-                        &DrawParameters::default(),
-                    )
-                    .expect("Could not draw frame");
+                let draw_result =
+                    self.state
+                        .draw(&mut frame, &self.uniform_mismatches, shader_timer.as_ref());
+                // shown in the error overlay instead of taking the whole program down. Printed once, not every frame
+                let draw_error = draw_result.err().map(|err| err.to_string());
+                if draw_error != self.draw_error {
+                    if let Some(err) = &draw_error {
+                        eprintln!("[ERROR] Could not draw the shader: {}", err);
+                    }
+                    self.draw_error = draw_error;
+                }
+
+                self.text_renderer.render_hud(&self.display, &mut frame);
 
-                if let Some(err) = &self.last_error {
+                if !self.info_text.is_empty() {
                     self.text_renderer
-                        .render_text(&self.display, &mut frame, err);
+                        .render_info(&self.display, &mut frame, &self.info_text);
                 }
 
+                let error_lines = self.error_lines();
+                if !error_lines.is_empty() {
+                    let (width, height) = frame.get_dimensions();
+                    let area = match self.error_display {
+                        ErrorDisplay::Overlay => Rect {
+                            left: 0,
+                            bottom: 0,
+                            width,
+                            height,
+                        },
+                        ErrorDisplay::Panel => {
+                            let panel_width = side_panel_width(width);
+                            Rect {
+                                left: width - panel_width,
+                                bottom: 0,
+                                width: panel_width,
+                                height,
+                            }
+                        }
+                    };
+                    self.text_renderer
+                        .render_text(&self.display, &mut frame, &error_lines, area);
+                }
+
+                // before finishing, as that waits for vsync
+                let cpu_time = draw_start.elapsed();
                 frame.finish().expect("Could not switch framebuffers");
                 self.display.flush();
                 self.should_rerender = false;
+
+                self.frame_stats.record_frame(draw_start, cpu_time);
+                if let Some(timer) = shader_timer {
+                    self.pending_gpu_timers.push_back(vec![("shader", timer)]);
+                }
+                self.collect_gpu_timers();
+                self.publish_frame_stats();
+
+                self.send_state_update(StateUpdateCommand::FrameRendered(
+                    self.frame_count,
+                    (Instant::now() - frame_start).as_secs_f32(),
+                ));
+                self.frame_count += 1;
             }
             _ => {}
         }
     }
 
     fn about_to_wait(&mut self, _event_loop: &glium::winit::event_loop::ActiveEventLoop) {
+        if let Some((x, y)) = self.pending_cursor_position.take() {
+            self.send_state_update(StateUpdateCommand::MouseMoved(x, y));
+        }
+
         self.window.request_redraw();
     }
 }
+
+/// Rows to scroll the error overlay for the key, if it scrolls.
+fn overlay_scroll_shortcut(key: &Key) -> Option<isize> {
+    match key {
+        Key::Named(NamedKey::PageUp) => Some(-10),
+        Key::Named(NamedKey::PageDown) => Some(10),
+        _ => None,
+    }
+}
+
+fn severity_color(severity: Severity) -> (u8, u8, u8) {
+    match severity {
+        Severity::Error => ERROR_COLOR,
+        Severity::Warning => WARNING_COLOR,
+        Severity::Info => INFO_COLOR,
+    }
+}
+
+/// GLSL name of a uniform type, e.g, "vec3". Types without a common name are shown like glium names them.
+fn glsl_type_name(ty: UniformType) -> String {
+    let name = match ty {
+        UniformType::Float => "float",
+        UniformType::FloatVec2 => "vec2",
+        UniformType::FloatVec3 => "vec3",
+        UniformType::FloatVec4 => "vec4",
+        UniformType::Int => "int",
+        UniformType::IntVec2 => "ivec2",
+        UniformType::IntVec3 => "ivec3",
+        UniformType::IntVec4 => "ivec4",
+        UniformType::UnsignedInt => "uint",
+        UniformType::Bool => "bool",
+        UniformType::FloatMat2 => "mat2",
+        UniformType::FloatMat3 => "mat3",
+        UniformType::FloatMat4 => "mat4",
+        UniformType::Sampler1d => "sampler1D",
+        UniformType::Sampler2d => "sampler2D",
+        UniformType::Sampler3d => "sampler3D",
+        UniformType::SamplerCube => "samplerCube",
+        other => return format!("{:?}", other),
+    };
+    name.to_string()
+}
+
+/// Active uniforms of a program, with their GLSL types.
+fn declared_uniforms(program: &Program) -> DeclaredUniforms {
+    program
+        .uniforms()
+        .map(|(name, uniform)| (name.clone(), glsl_type_name(uniform.ty)))
+        .collect()
+}
+
+/// Width of the error panel on a window of the given width. Wide enough for a line of code, but leaves most of the shader visible.
+fn side_panel_width(window_width: u32) -> u32 {
+    (window_width * 2 / 5).max(360).min(window_width)
+}
+
+/// Stores a shader that compiled, so it can be shown if the file is broken at the next start.
+fn remember_shader(cache: &ShaderCache, filename: &str, source: &str) {
+    if let Err(err) = cache.store(Path::new(filename), source) {
+        eprintln!("[WARNING] Could not remember the shader source: {}", err);
+    }
+}
+
+/// Whether the key is the given character, ignoring case.
+fn is_character(key: &Key, character: &str) -> bool {
+    matches!(key, Key::Character(key) if key.eq_ignore_ascii_case(character))
+}
+
+/// Reads the fragment shader source from file.
+fn read_shader(filename: &str) -> String {
+    fs::read_to_string(filename).expect("Could not read fragment shader!")
+}
+
+/// Whether the key does something in the window when pressed together with Ctrl.
+fn is_shortcut(key: &Key) -> bool {
+    ["e", "f", "u", "s"]
+        .iter()
+        .any(|character| is_character(key, character))
+        || time_shortcut(key).is_some()
+}
+
+/// Clock control for the key, when pressed together with Ctrl.
+fn time_shortcut(key: &Key) -> Option<TimeControl> {
+    match key {
+        Key::Named(NamedKey::Space) => Some(TimeControl::TogglePause),
+        Key::Named(NamedKey::ArrowLeft) => Some(TimeControl::Skip(-1.0)),
+        Key::Named(NamedKey::ArrowRight) => Some(TimeControl::Skip(1.0)),
+        Key::Named(NamedKey::ArrowDown) => Some(TimeControl::ScaleBy(0.5)),
+        Key::Named(NamedKey::ArrowUp) => Some(TimeControl::ScaleBy(2.0)),
+        Key::Named(NamedKey::Home) => Some(TimeControl::Reset),
+        _ => None,
+    }
+}
+
+/// Readable name of a key, e.g, "a" or "Space".
+fn key_name(key: &Key) -> String {
+    match key {
+        Key::Character(character) => character.to_string(),
+        Key::Named(named_key) => format!("{:?}", named_key),
+        _ => "Unidentified".to_string(),
+    }
+}
@@ -0,0 +1,391 @@
+//! Wire formats the REPL speaks. The text protocol is made for humans with `nc` or a comint buffer, while the JSON protocol is made for editors and other tools.
+//!
+//! In the JSON protocol each request is a line like `{"id": 1, "code": "(+ 1 2)"}`, and is answered with a line like
+//! `{"id": 1, "value": "3", "status": "done"}`. Failed evaluations have an `error` field instead of `value`, and
+//! anything printed while evaluating is in a `stdout` field. Messages nobody asked for, like warnings, have no id.
+//! Renderer events, like shader reloads, have an `event` field with the name of the event.
+
+use serde_json::{Value, json};
+
+use crate::diagnostics::Diagnostic;
+
+/// Protocol used by all REPL connections.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Protocol {
+    /// Plain lines of Scheme code, answered with plain text and a `> ` prompt
+    #[default]
+    Text,
+    /// Newline delimited JSON messages
+    Json,
+}
+
+/// Outcome of evaluating the code of a request. The value or the error message, as text, and what was printed on the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalResult {
+    pub value: Result<String, String>,
+    pub stdout: String,
+}
+
+/// Something that happened in the renderer, which all clients are told about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    ShaderReloaded,
+    /// The full compile log, and the diagnostics parsed from it
+    ShaderCompileFailed(String, Vec<Diagnostic>),
+}
+
+/// Code to evaluate, and an id to tag the reply with. The text protocol has no ids.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub id: Value,
+    pub code: String,
+}
+
+impl Protocol {
+    /// Parses protocol names as given on the command line.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "text" => Ok(Protocol::Text),
+            "json" => Ok(Protocol::Json),
+            _ => Err(format!(
+                "Unknown REPL protocol {}. Should be text or json",
+                name
+            )),
+        }
+    }
+
+    /// Written before reading each request, if any.
+    pub fn prompt(&self) -> Option<&'static str> {
+        match self {
+            Protocol::Text => Some("> "),
+            Protocol::Json => None,
+        }
+    }
+
+    /// Written while a request is incomplete, if requests can span several lines.
+    pub fn continuation_prompt(&self) -> Option<&'static str> {
+        match self {
+            Protocol::Text => Some("... "),
+            Protocol::Json => None,
+        }
+    }
+
+    /// Reads a request from a line sent by the client.
+    pub fn decode_request(&self, line: &str) -> Result<Request, String> {
+        match self {
+            Protocol::Text => Ok(Request {
+                id: Value::Null,
+                code: line.to_string(),
+            }),
+            Protocol::Json => {
+                let request: Value = serde_json::from_str(line)
+                    .map_err(|err| format!("Invalid request: {}", err))?;
+                let code = request
+                    .get("code")
+                    .and_then(Value::as_str)
+                    .ok_or("Invalid request: code should be a string")?;
+                Ok(Request {
+                    id: request.get("id").cloned().unwrap_or(Value::Null),
+                    code: code.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Reply to the request with the given id, including the line ending.
+    pub fn encode_reply(&self, id: &Value, result: &EvalResult) -> String {
+        match self {
+            Protocol::Text => match &result.value {
+                Ok(value) => format!("{}{}\n", result.stdout, value),
+                Err(err) => format!("{}ERROR: Evaluation failed: {}\n", result.stdout, err),
+            },
+            Protocol::Json => {
+                let mut reply = json!({"id": id, "status": "done"});
+                match &result.value {
+                    Ok(value) => reply["value"] = json!(value),
+                    Err(err) => reply["error"] = json!(err),
+                }
+                if !result.stdout.is_empty() {
+                    reply["stdout"] = json!(result.stdout);
+                }
+                format!("{}\n", reply)
+            }
+        }
+    }
+
+    /// Printed output a client didn't ask for, e.g, from a dynamic uniform.
+    pub fn encode_output(&self, output: &str) -> String {
+        match self {
+            Protocol::Text => self.unprompted(output),
+            Protocol::Json => format!("{}\n", json!({ "stdout": output })),
+        }
+    }
+
+    /// Renderer event a client didn't ask for, including the line ending.
+    pub fn encode_notification(&self, notification: &Notification) -> String {
+        match (self, notification) {
+            (Protocol::Text, Notification::ShaderReloaded) => {
+                self.unprompted("[INFO] Shader reloaded")
+            }
+            (Protocol::Text, Notification::ShaderCompileFailed(_, diagnostics)) => {
+                let mut text = "[ERROR] Shader compilation failed:".to_string();
+                for diagnostic in diagnostics {
+                    text.push_str(&format!("\n{}", diagnostic));
+                }
+                self.unprompted(&text)
+            }
+            (Protocol::Json, Notification::ShaderReloaded) => {
+                format!("{}\n", json!({ "event": "reload" }))
+            }
+            (Protocol::Json, Notification::ShaderCompileFailed(log, diagnostics)) => {
+                let diagnostics: Vec<Value> = diagnostics.iter().map(Diagnostic::to_json).collect();
+                format!(
+                    "{}\n",
+                    json!({ "event": "compile-error", "log": log, "diagnostics": diagnostics })
+                )
+            }
+        }
+    }
+
+    /// Message to a client that didn't ask for it, including the line ending.
+    pub fn encode_message(&self, message: &str) -> String {
+        match self {
+            Protocol::Text => self.unprompted(message),
+            Protocol::Json => format!("{}\n", json!({ "message": message })),
+        }
+    }
+
+    /// Text the client didn't ask for, on lines of its own as the client may be in the middle of typing, followed by the
+    /// prompt again.
+    fn unprompted(&self, text: &str) -> String {
+        let line_ending = if text.ends_with('\n') { "" } else { "\n" };
+        format!(
+            "\n{}{}{}",
+            text,
+            line_ending,
+            self.prompt().unwrap_or_default()
+        )
+    }
+}
+
+/// Whether the Scheme code has no unclosed parentheses, strings or block comments, so it's ready to be evaluated.
+/// Too many closing parentheses also counts as complete as soon as they are found, to let the evaluation report the error
+/// instead of waiting for more.
+pub fn is_complete_expression(code: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                depth -= 1;
+                if depth < 0 {
+                    return true;
+                }
+            }
+            // line comment, skipped to the end of the line
+            ';' => {
+                chars.find(|c| *c == '\n');
+            }
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(_) => {}
+                    None => return false,
+                }
+            },
+            '#' => match chars.peek() {
+                // character literals like #\( don't count
+                Some('\\') => {
+                    chars.next();
+                    chars.next();
+                }
+                // block comment
+                Some('|') => {
+                    chars.next();
+                    let mut prev = ' ';
+                    loop {
+                        match chars.next() {
+                            Some('#') if prev == '|' => break,
+                            Some(c) => prev = c,
+                            None => return false,
+                        }
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{EvalResult, Notification, Protocol, Request, is_complete_expression};
+    use crate::diagnostics::parse_compile_log;
+
+    #[test]
+    fn complete_expression_test() {
+        assert!(is_complete_expression("(+ 1 2)\n"));
+        assert!(is_complete_expression("(define x 1) (define y 2)\n"));
+        assert!(is_complete_expression("\n"));
+        assert!(!is_complete_expression("(define (square x)\n"));
+        assert!(is_complete_expression("(define (square x)\n  (* x x))\n"));
+
+        // parentheses in strings, comments and characters don't count
+        assert!(is_complete_expression("(display \"(\")\n"));
+        assert!(is_complete_expression("(display \"\\\"(\")\n"));
+        assert!(!is_complete_expression("(display \"hi\n"));
+        assert!(!is_complete_expression("(+ 1 ; 2)\n"));
+        assert!(is_complete_expression("(+ 1 ; (\n 2)\n"));
+        assert!(is_complete_expression("(char->integer #\\()\n"));
+        assert!(!is_complete_expression("#| (+ 1 2)\n"));
+        assert!(is_complete_expression("#| ( |# (+ 1 2)\n"));
+
+        // let the evaluation complain about these
+        assert!(is_complete_expression("(+ 1 2))\n"));
+        // opened again after a stray closing parenthesis, which is an error anyway
+        assert!(is_complete_expression(") (\n"));
+        assert!(is_complete_expression("(a))((b\n"));
+        assert!(is_complete_expression(")\n(define x\n"));
+    }
+
+    #[test]
+    fn text_protocol_test() {
+        let protocol = Protocol::Text;
+        assert_eq!(
+            Ok(Request {
+                id: Value::Null,
+                code: "(+ 1 2)\n".to_string()
+            }),
+            protocol.decode_request("(+ 1 2)\n")
+        );
+        assert_eq!(
+            "3\n",
+            protocol.encode_reply(
+                &Value::Null,
+                &EvalResult {
+                    value: Ok("3".to_string()),
+                    stdout: String::new(),
+                }
+            )
+        );
+        assert_eq!(
+            "printed\nERROR: Evaluation failed: oops\n",
+            protocol.encode_reply(
+                &Value::Null,
+                &EvalResult {
+                    value: Err("oops".to_string()),
+                    stdout: "printed\n".to_string(),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn json_protocol_test() {
+        let protocol = Protocol::Json;
+        assert_eq!(
+            Ok(Request {
+                id: json!(7),
+                code: "(define x\n  2)".to_string()
+            }),
+            protocol.decode_request(r#"{"id": 7, "code": "(define x\n  2)"}"#)
+        );
+        assert!(protocol.decode_request("(+ 1 2)").is_err());
+        assert!(protocol.decode_request(r#"{"id": 7}"#).is_err());
+
+        let reply: Value = serde_json::from_str(&protocol.encode_reply(
+            &json!("a"),
+            &EvalResult {
+                value: Ok("3".to_string()),
+                stdout: String::new(),
+            },
+        ))
+        .unwrap();
+        assert_eq!(json!({"id": "a", "value": "3", "status": "done"}), reply);
+
+        let reply: Value = serde_json::from_str(&protocol.encode_reply(
+            &json!("b"),
+            &EvalResult {
+                value: Err("oops".to_string()),
+                stdout: "printed\n".to_string(),
+            },
+        ))
+        .unwrap();
+        assert_eq!(
+            json!({"id": "b", "error": "oops", "stdout": "printed\n", "status": "done"}),
+            reply
+        );
+    }
+
+    #[test]
+    fn notification_test() {
+        let log = "0:3(1): error: syntax error\n";
+        let failed = Notification::ShaderCompileFailed(
+            log.to_string(),
+            parse_compile_log(log, "plasma.frag"),
+        );
+        assert_eq!(
+            "\n[ERROR] Shader compilation failed:\nplasma.frag:3:1: error: syntax error\n> ",
+            Protocol::Text.encode_notification(&failed)
+        );
+        assert_eq!(
+            "\n[INFO] Shader reloaded\n> ",
+            Protocol::Text.encode_notification(&Notification::ShaderReloaded)
+        );
+
+        let notification: Value =
+            serde_json::from_str(&Protocol::Json.encode_notification(&failed)).unwrap();
+        assert_eq!(
+            json!({
+                "event": "compile-error",
+                "log": log,
+                "diagnostics": [{
+                    "file": "plasma.frag",
+                    "line": 3,
+                    "column": 1,
+                    "severity": "error",
+                    "message": "syntax error"
+                }]
+            }),
+            notification
+        );
+        let notification: Value = serde_json::from_str(
+            &Protocol::Json.encode_notification(&Notification::ShaderReloaded),
+        )
+        .unwrap();
+        assert_eq!(json!({"event": "reload"}), notification);
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(Ok(Protocol::Json), Protocol::parse("json"));
+        assert_eq!(Ok(Protocol::Text), Protocol::parse("text"));
+        assert!(Protocol::parse("nrepl").is_err());
+    }
+
+    #[test]
+    fn unprompted_text_test() {
+        // all on fresh lines, with the prompt again after them
+        assert_eq!(
+            "\n[WARNING] slow\n> ",
+            Protocol::Text.encode_message("[WARNING] slow")
+        );
+        assert_eq!("\ntick\n> ", Protocol::Text.encode_output("tick"));
+        assert_eq!(
+            "\ntick\ntock\n> ",
+            Protocol::Text.encode_output("tick\ntock\n")
+        );
+        assert_eq!(
+            "{\"stdout\":\"tick\"}\n",
+            Protocol::Json.encode_output("tick")
+        );
+    }
+}
@@ -1,18 +1,22 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Display,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, Read, Write},
     net::TcpListener,
+    path::PathBuf,
+    process,
     sync::{
-        Arc, Mutex,
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
         mpsc::{Receiver, Sender, channel},
     },
     thread,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use image::RgbaImage;
 use nalgebra::{Matrix4, RowVector4};
+use serde_json::Value;
 use steel::{
     SteelVal,
     parser::ast::IteratorExtensions,
@@ -21,163 +25,591 @@ use steel::{
 };
 use steel_derive::Steel;
 
-use crate::command::{RenderCommand, StateUpdateCommand, UniformValue};
+use crate::{
+    cli::{DEFAULT_REPL_HOST, DEFAULT_REPL_PORT},
+    clock::Clock,
+    command::{
+        MAX_TEXT_SIZE, RenderCommand, RenderSnapshot, Reply, StateUpdateCommand, TextElement,
+        TimeControl, UniformValue,
+    },
+    diagnostics::Diagnostic,
+    export::{ExportSettings, FPS_RANGE},
+    protocol::{EvalResult, Notification, Protocol, is_complete_expression},
+    stats::{FrameStatsSummary, millis},
+    timeline::{Curve, Timeline},
+    uniform_check::{DeclaredUniforms, UniformProblem, check_uniform},
+};
 
 /// The scheme process' information on the state of the renderer.
 #[derive(Default)]
 struct RenderState {
     // width, height
     screen_size: (u32, u32),
+    // from the last failed shader compilation, empty if it compiled
+    diagnostics: Vec<Diagnostic>,
+    frame_stats: FrameStatsSummary,
+    // active uniforms of the shader in use. None until a shader has compiled
+    shader_uniforms: Option<DeclaredUniforms>,
+}
+
+/// A connected REPL client. Expressions and their results are sent over channels, while the writer is used for messages the client didn't ask for, like warnings.
+struct ReplClient {
+    input: Receiver<String>,
+    output: Sender<EvalResult>,
+    // shared with the client thread to not mix up writes
+    writer: ReplWriter,
+    protocol: Protocol,
+}
+
+/// Writing end of a REPL connection, whichever transport it uses.
+type ReplWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Scheme callbacks registered with `on-event!`, keyed by event name.
+type EventCallbackTable = Arc<Mutex<HashMap<String, Vec<SteelVal>>>>;
+
+/// Update interval for dynamic uniforms, unless configured otherwise.
+pub const DEFAULT_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time a dynamic uniform function may run each update, unless configured otherwise.
+pub const DEFAULT_EXECUTION_BUDGET: Duration = Duration::from_millis(10);
+
+/// Number of failures or budget overruns in a row before a dynamic uniform is disabled.
+const MAX_STRIKES: u32 = 3;
+
+/// How long to wait for the renderer to answer a request. It answers between frames, so this is plenty.
+const RENDERER_REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait for a screenshot to be captured. Rendering and reading back a large image takes a moment.
+const SCREENSHOT_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health of a dynamic uniform. Consecutive failures are counted to disable the ones that misbehave, and the last error is kept for reporting.
+#[derive(Default)]
+struct DynamicUniformStatus {
+    errors: u32,
+    overruns: u32,
+    last_error: Option<String>,
+}
+
+/// Interrupts the Scheme VM when a dynamic uniform function runs past its budget, as it may never return. Waits for the
+/// deadline in a thread of its own, which sleeps while nothing is armed and stops when the watchdog is dropped.
+struct Watchdog {
+    state: Arc<(Mutex<WatchState>, Condvar)>,
+    interrupted: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    // when to interrupt, if armed
+    deadline: Option<Instant>,
+    stopped: bool,
+}
+
+impl Watchdog {
+    /// Starts watching. `interrupted` should be the flag the VM checks while running.
+    fn new(interrupted: Arc<AtomicBool>) -> Self {
+        let state: Arc<(Mutex<WatchState>, Condvar)> = Arc::default();
+        {
+            let state = Arc::clone(&state);
+            let interrupted = Arc::clone(&interrupted);
+            thread::spawn(move || watch(&state, &interrupted));
+        }
+        Self { state, interrupted }
+    }
+
+    fn arm(&self, timeout: Duration) {
+        let (state, wakeup) = &*self.state;
+        state.lock().unwrap().deadline = Some(Instant::now() + timeout);
+        wakeup.notify_one();
+    }
+
+    /// Stops watching, and gives whether the VM was interrupted since armed. The VM may run again afterwards.
+    fn disarm(&self) -> bool {
+        // the lock keeps the watchdog from interrupting after this
+        let mut state = self.state.0.lock().unwrap();
+        state.deadline = None;
+        self.interrupted.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (state, wakeup) = &*self.state;
+        state.lock().unwrap().stopped = true;
+        wakeup.notify_one();
+    }
+}
+
+/// Loop of the watchdog thread. Sets the flag when the deadline passes, until the watchdog is dropped.
+fn watch(state: &(Mutex<WatchState>, Condvar), interrupted: &AtomicBool) {
+    let (state, wakeup) = state;
+    let mut state = state.lock().unwrap();
+    while !state.stopped {
+        state = match state.deadline {
+            None => wakeup.wait(state).unwrap(),
+            Some(deadline) if Instant::now() >= deadline => {
+                interrupted.store(true, Ordering::SeqCst);
+                state.deadline = None;
+                state
+            }
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                wakeup.wait_timeout(state, timeout).unwrap().0
+            }
+        };
+    }
 }
 
-type ReplInputOutputPair = (Receiver<String>, Sender<String>);
+/// How often a dynamic uniform is recalculated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UpdateRate {
+    /// Follow the global update interval
+    Default,
+    Interval(Duration),
+    /// Once for every frame the renderer draws
+    EveryFrame,
+}
 
-/// Scheme REPL running as a process over the network on port 42069. Sends messages on a channel.
+/// Names of the events Scheme code can register callbacks for.
+const EVENT_NAMES: [&str; 8] = [
+    "resize",
+    "reload",
+    "compile-error",
+    "key-press",
+    "key-release",
+    "mouse-move",
+    "mouse-click",
+    "frame",
+];
+
+/// Scheme REPL running as a process over the network, on port 42069 by default. Sends messages on a channel.
 pub struct NetworkScheme {
     scheme_vm: Engine,
 
     /// Whether previous expression was an error
     prev_was_error: bool,
+
+    /// Events forwarded from the renderer, waiting for their callbacks to be run
+    events: Receiver<StateUpdateCommand>,
+    event_callbacks: EventCallbackTable,
+
+    // dynamic uniform scheduling. Shared with the Scheme functions that configure them
+    update_interval: Arc<Mutex<Duration>>,
+    update_rates: Arc<Mutex<HashMap<String, UpdateRate>>>,
+    last_updates: Arc<Mutex<HashMap<String, Instant>>>,
+    dynamic_uniforms: Arc<Mutex<HashMap<String, SteelVal>>>,
+    execution_budget: Arc<Mutex<Duration>>,
+    // stops dynamic uniform functions that run over the budget
+    watchdog: Watchdog,
+    uniform_status: Arc<Mutex<HashMap<String, DynamicUniformStatus>>>,
+
+    /// Whether dynamic uniforms are evaluated on frame ticks from the renderer instead of on a timer
+    frame_sync: Arc<Mutex<bool>>,
+    // index and time of the frame currently being synchronised
+    current_frame: Arc<Mutex<(u64, f32)>>,
+    render_commands: Sender<RenderCommand>,
+    // whether anything answers requests sent with render_commands. Only cleared when rendering without a window
+    #[cfg_attr(target_vendor = "apple", allow(dead_code))]
+    has_renderer: Arc<AtomicBool>,
+
+    timeline: Arc<Mutex<Timeline>>,
+    clock: Arc<Mutex<Clock>>,
+    // printed with display and friends since last taken
+    output: Arc<Mutex<String>>,
+    render_state: Arc<Mutex<RenderState>>,
+    // time of the clock and whether it was paused before an export took it over. Some while exporting
+    clock_before_export: Option<(f32, bool)>,
+    // dynamic uniforms already warned about not being used by the shader, to not repeat it every update. Cleared when the
+    // shader changes
+    unknown_uniform_warnings: HashSet<String>,
+
+    repl_clients: Arc<Mutex<Vec<ReplClient>>>,
+    repl_host: String,
+    repl_port: u16,
+    repl_socket: Option<PathBuf>,
+    repl_protocol: Protocol,
 }
 
 impl NetworkScheme {
     /// The only user facing function. Starts a network process and runs the main loop. Blocks, so recommended to run this in its own thread.
     pub fn main_loop(mut self) {
-        // input and output ports for the repl thread
-        let repl_channels: Arc<Mutex<Vec<ReplInputOutputPair>>> = Arc::new(Mutex::new(Vec::new()));
-
-        {
-            let repl_channels = Arc::clone(&repl_channels);
-            thread::spawn(move || {
-                let listener =
-                    TcpListener::bind("127.0.0.1:42069").expect("Could not bind to port 42069!");
-
-                #[allow(clippy::manual_flatten)]
-                for stream in listener.incoming() {
-                    if let Ok(mut stream) = stream {
-                        // create a command port to send to the repl
-                        let (repl_input_sender, repl_input_receiver) = channel();
-                        let (repl_output_sender, repl_output_receiver) = channel();
-
-                        // Add our own communication channels to the REPLs list of users
-                        {
-                            repl_channels
-                                .lock()
-                                .unwrap()
-                                .push((repl_input_receiver, repl_output_sender));
-                        }
+        match self.repl_socket.clone() {
+            Some(path) => self.listen_unix_socket(path),
+            None => self.listen_tcp(),
+        }
 
-                        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        // main thread repl and dynamic updates loop
+        loop {
+            // while exporting, uniforms are only updated for each exported frame
+            if !self.is_frame_synced() && !self.is_exporting() {
+                self.run_dynamic_updates();
+
+                // while playing, the timeline is updated each rendered frame. Other changes, like scrubbing, should show right away
+                if self.timeline.lock().unwrap().is_dirty() {
+                    self.run_timeline(self.time());
+                }
+            }
+            self.dispatch_events();
+            // nobody asked for this output, so everyone gets it
+            self.broadcast_output();
+
+            // collected first to not hold the lock while evaluating
+            let requests: Vec<(String, Sender<EvalResult>)> = self
+                .repl_clients
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|client| {
+                    client
+                        .input
+                        .try_recv()
+                        .ok()
+                        .map(|msg| (msg, client.output.clone()))
+                })
+                .collect();
+            for (msg, output) in requests {
+                let result = self.evaluate(msg);
+                // the client may have disconnected while waiting. Nothing to do about that
+                let _ = output.send(result);
+            }
 
-                        // run the repl in a new thread, and let it crash if the user disconnects unexpectedly.
-                        thread::spawn(move || {
-                            loop {
-                                // write prompt
-                                stream.write_all(b"> ").unwrap();
+            // no need to spin a whole CPU core while waiting for work
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
 
-                                let mut buffer = String::new();
-                                if let Ok(bytes_read) = reader.read_line(&mut buffer) {
-                                    if bytes_read == 0 {
-                                        continue;
-                                    }
+    /// Accepts REPL clients over TCP in a background thread.
+    fn listen_tcp(&self) {
+        let listener =
+            TcpListener::bind((self.repl_host.as_str(), self.repl_port)).unwrap_or_else(|err| {
+                eprintln!(
+                    "[ERROR] Could not start the REPL on {}:{}: {}",
+                    self.repl_host, self.repl_port, err
+                );
+                process::exit(1);
+            });
+        // machine readable line for editors to find the REPL, as the port may have been picked by the OS
+        println!("SEPL_REPL=tcp://{}", listener.local_addr().unwrap());
 
-                                    // run the command
-                                    repl_input_sender.send(buffer).unwrap();
-                                    let result = repl_output_receiver.recv().unwrap();
+        let repl_clients = Arc::clone(&self.repl_clients);
+        let protocol = self.repl_protocol;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let writer = stream.try_clone().unwrap();
+                spawn_repl_client(&repl_clients, protocol, stream, writer);
+            }
+        });
+    }
 
-                                    //let result = self.eval(buffer);
-                                    stream.write_all(&result.into_bytes()).unwrap();
+    /// Accepts REPL clients over a Unix domain socket in a background thread. Only the current user may connect.
+    #[cfg(unix)]
+    fn listen_unix_socket(&self, path: PathBuf) {
+        use std::{fs, os::unix::net::UnixStream};
 
-                                    stream.flush().unwrap();
-                                }
-                            }
-                        });
-                    }
-                }
-            });
+        // a socket file left behind by a SEPL that didn't exit cleanly is in the way. One that still answers is in use
+        if path.exists() && UnixStream::connect(&path).is_err() {
+            let _ = fs::remove_file(&path);
         }
+        let listener = bind_private_unix_socket(&path).unwrap_or_else(|err| {
+            eprintln!(
+                "[ERROR] Could not start the REPL on {}: {}",
+                path.display(),
+                err
+            );
+            process::exit(1);
+        });
+        println!("SEPL_REPL=unix://{}", path.display());
 
-        // main thread repl and dynamic updates loop
-        let mut prev_time = Instant::now();
-        loop {
-            // TODO: tunable interval for older computers?
-            if (Instant::now() - prev_time).as_millis() >= 50 {
-                self.run_dynamic_updates().unwrap();
-                prev_time = Instant::now();
+        let repl_clients = Arc::clone(&self.repl_clients);
+        let protocol = self.repl_protocol;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let writer = stream.try_clone().unwrap();
+                spawn_repl_client(&repl_clients, protocol, stream, writer);
             }
+        });
+    }
 
-            for (input, output) in repl_channels.lock().unwrap().iter() {
-                if let Ok(msg) = input.try_recv() {
-                    let result = self.eval(msg);
-                    output.send(result).unwrap();
-                }
-            }
+    #[cfg(not(unix))]
+    fn listen_unix_socket(&self, path: PathBuf) {
+        eprintln!(
+            "[ERROR] Could not start the REPL on {}: Unix sockets are not supported on this platform",
+            path.display()
+        );
+        process::exit(1);
+    }
+
+    /// Sends a message to all connected REPL clients, even if they didn't ask for it. Also printed to console. Clients that can't be written to are dropped.
+    fn broadcast(&self, message: &str) {
+        eprintln!("{}", message);
+        self.send_to_all_clients(|protocol| protocol.encode_message(message));
+    }
+
+    /// Sends output printed outside of REPL requests, e.g, by dynamic uniforms and callbacks, to all connected REPL clients. Also printed to console.
+    fn broadcast_output(&self) {
+        let output = self.take_output();
+        if !output.is_empty() {
+            print!("{}", output);
+            self.send_to_all_clients(|protocol| protocol.encode_output(&output));
         }
     }
 
+    /// Tells all connected REPL clients about something that happened in the renderer. Already printed to console by the renderer.
+    fn notify_clients(&self, notification: &Notification) {
+        self.send_to_all_clients(|protocol| protocol.encode_notification(notification));
+    }
+
+    /// Writes to each client what `encode` gives for its protocol. Clients that can't be written to are dropped.
+    fn send_to_all_clients(&self, encode: impl Fn(Protocol) -> String) {
+        self.repl_clients.lock().unwrap().retain(|client| {
+            let mut writer = client.writer.lock().unwrap();
+            writer
+                .write_all(encode(client.protocol).as_bytes())
+                .and_then(|_| writer.flush())
+                .is_ok()
+        });
+    }
+
+    /// Output printed by Scheme code since last call.
+    fn take_output(&self) -> String {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+
+    /// Turns frame synchronised dynamic uniforms on or off, both here and in the renderer. Can also be changed from Scheme with `set-frame-sync!`.
+    pub fn set_frame_sync(&mut self, enabled: bool) {
+        *self.frame_sync.lock().unwrap() = enabled;
+        self.render_commands
+            .send(RenderCommand::SetFrameSync(enabled))
+            .unwrap();
+    }
+
+    fn is_frame_synced(&self) -> bool {
+        *self.frame_sync.lock().unwrap()
+    }
+
+    fn is_exporting(&self) -> bool {
+        self.clock_before_export.is_some()
+    }
+
+    /// Current time of the global clock, in seconds.
+    fn time(&self) -> f32 {
+        self.clock.lock().unwrap().time()
+    }
+
+    /// Sets the global interval between dynamic uniform updates. Can also be changed from Scheme with `set-update-interval!`.
+    pub fn set_update_interval(&mut self, interval: Duration) {
+        *self.update_interval.lock().unwrap() = interval;
+    }
+
+    /// Sets the protocol REPL clients are spoken to with. Must be called before `main_loop`.
+    pub fn set_repl_protocol(&mut self, protocol: Protocol) {
+        self.repl_protocol = protocol;
+    }
+
+    /// Makes the REPL listen on a Unix domain socket at the given path instead of TCP. Must be called before `main_loop`.
+    pub fn set_repl_socket(&mut self, path: PathBuf) {
+        self.repl_socket = Some(path);
+    }
+
+    /// Sets where the REPL listens for clients. Port 0 lets the OS pick a free one. Must be called before `main_loop`.
+    pub fn set_repl_address(&mut self, host: String, port: u16) {
+        self.repl_host = host;
+        self.repl_port = port;
+    }
+
     pub fn new_env(
         input_port: Receiver<StateUpdateCommand>,
         output_port: Sender<RenderCommand>,
     ) -> Self {
         let mut scheme_vm = Engine::new();
-        let start_time = Instant::now();
-
-        scheme_vm.register_fn("set-uniform!", move |name: String, value: SteelVal| {
-            // TODO: better error handling!
-            output_port
-                .send(RenderCommand::SetUniform(
-                    name.clone(),
-                    match value {
-                        SteelVal::NumV(num) => UniformValue::Float(num as f32),
-                        SteelVal::ListV(list) => {
-                            // TODO: make prettier. This is fucking ugly
-                            let all_nums: Vec<Option<f32>> = list
-                                .iter()
-                                .map(|elem| match elem {
-                                    SteelVal::NumV(num) => Some(*num as f32),
-                                    _ => None,
-                                })
-                                .collect();
-                            if all_nums.len() == 3 && all_nums.iter().all(|elem| elem.is_some()) {
-                                UniformValue::Vector3(
-                                    all_nums[0].unwrap(),
-                                    all_nums[1].unwrap(),
-                                    all_nums[2].unwrap(),
-                                )
-                            } else {
-                                return Err(
-                                    "uniform only support coercing 3 element lists to vec3",
-                                );
-                            }
-                        }
-                        SteelVal::Custom(val) => {
-                            if let Some(matrix) = val.borrow().as_any_ref().downcast_ref::<Matrix>()
-                            {
-                                UniformValue::Matrix(matrix.into())
-                            } else if let Some(texture) =
-                                val.borrow().as_any_ref().downcast_ref::<Texture>()
-                            {
-                                UniformValue::RgbaTexture2D(texture.image.clone())
-                            } else {
-                                unreachable!("Should never happen")
-                            }
-                        }
-                        _ => return Err("thats a paddlin"),
-                    },
-                ))
-                .unwrap();
-            Ok(())
-        });
+        let interrupted = Arc::new(AtomicBool::new(false));
+        scheme_vm.with_interrupted(Arc::clone(&interrupted));
+        let render_state = Arc::new(Mutex::new(RenderState::default()));
+        // cleared when nothing answers requests to the renderer
+        let has_renderer = Arc::new(AtomicBool::new(true));
+        // capture printed output, to send it to the REPL clients instead of the console
+        let output: Arc<Mutex<String>> = Arc::default();
 
-        // dynamic uniform
-        // defining a global variable, which seems to be the easiest way while avoiding ownership and thread deadlocks.
-        // TODO: prettify so code is easier to read
-        scheme_vm
-            .run("(define DYNAMIC_UNIFORM_TABLE (hash))".to_string())
-            .expect("Could should NOT fail");
-        scheme_vm.run("(define (set-dynamic-uniform! name func) (set! DYNAMIC_UNIFORM_TABLE (hash-insert DYNAMIC_UNIFORM_TABLE name func)))".to_string()).expect("Could should NOT fail");
-        // TODO: return an error if name don't exist
-        scheme_vm.run("(define (delete-dynamic-uniform! name) (set! DYNAMIC_UNIFORM_TABLE (hash-remove DYNAMIC_UNIFORM_TABLE name)))".to_string()).expect("Should NOT fail!");
+        {
+            let output_port = output_port.clone();
+            let render_state = Arc::clone(&render_state);
+            let output = Arc::clone(&output);
+            scheme_vm.register_fn(
+                "set-uniform!",
+                move |name: String, value: SteelVal| -> Result<(), String> {
+                    let value = uniform_value_from_steelval(value)?;
+                    if let Some(warning) = check_against_shader(&render_state, &name, &value)? {
+                        output
+                            .lock()
+                            .unwrap()
+                            .push_str(&format!("[WARNING] {}\n", warning));
+                    }
+
+                    output_port
+                        .send(RenderCommand::SetUniform(name, value))
+                        .unwrap();
+                    Ok(())
+                },
+            );
+        }
+
+        // dynamic uniforms, recalculated regularly from the main loop
+        let dynamic_uniforms: Arc<Mutex<HashMap<String, SteelVal>>> = Arc::default();
+        let uniform_status: Arc<Mutex<HashMap<String, DynamicUniformStatus>>> = Arc::default();
+        let update_rates: Arc<Mutex<HashMap<String, UpdateRate>>> = Arc::default();
+        let last_updates: Arc<Mutex<HashMap<String, Instant>>> = Arc::default();
+        {
+            let dynamic_uniforms = Arc::clone(&dynamic_uniforms);
+            let uniform_status = Arc::clone(&uniform_status);
+            let output_port = output_port.clone();
+            scheme_vm.register_fn(
+                "set-dynamic-uniform!",
+                move |name: String, func: SteelVal| -> Result<(), String> {
+                    validate_dynamic_uniform_function(&func)?;
+                    dynamic_uniforms.lock().unwrap().insert(name.clone(), func);
+
+                    // a new function gets a fresh start, even if the previous one was disabled
+                    clear_dynamic_uniform_status(&uniform_status, &name, &output_port);
+                    Ok(())
+                },
+            );
+        }
+        {
+            let dynamic_uniforms = Arc::clone(&dynamic_uniforms);
+            let uniform_status = Arc::clone(&uniform_status);
+            let update_rates = Arc::clone(&update_rates);
+            let last_updates = Arc::clone(&last_updates);
+            let output_port = output_port.clone();
+            scheme_vm.register_fn(
+                "delete-dynamic-uniform!",
+                move |name: String| -> Result<(), String> {
+                    let existed = dynamic_uniforms.lock().unwrap().remove(&name).is_some();
+                    // a disabled uniform is already gone, but its error is still shown
+                    let disabled = uniform_status.lock().unwrap().contains_key(&name);
+                    if !existed && !disabled {
+                        return Err(format!("No dynamic uniform named {}", name));
+                    }
+                    update_rates.lock().unwrap().remove(&name);
+                    last_updates.lock().unwrap().remove(&name);
+
+                    clear_dynamic_uniform_status(&uniform_status, &name, &output_port);
+                    Ok(())
+                },
+            );
+        }
+        {
+            let dynamic_uniforms = Arc::clone(&dynamic_uniforms);
+            scheme_vm.register_fn("dynamic-uniforms", move || {
+                let mut names: Vec<String> =
+                    dynamic_uniforms.lock().unwrap().keys().cloned().collect();
+                names.sort();
+                names
+            });
+        }
+
+        // how often dynamic uniforms are updated, globally and per uniform
+        let update_interval = Arc::new(Mutex::new(DEFAULT_UPDATE_INTERVAL));
+        {
+            let update_interval = Arc::clone(&update_interval);
+            scheme_vm.register_fn(
+                "set-update-interval!",
+                move |interval: SteelVal| -> Result<(), String> {
+                    *update_interval.lock().unwrap() = duration_from_millis(&interval)
+                        .ok_or("Update interval should be a positive number of milliseconds")?;
+                    Ok(())
+                },
+            );
+        }
+        {
+            let update_interval = Arc::clone(&update_interval);
+            scheme_vm.register_fn("get-update-interval", move || {
+                update_interval.lock().unwrap().as_secs_f64() * 1000.0
+            });
+        }
+        let execution_budget = Arc::new(Mutex::new(DEFAULT_EXECUTION_BUDGET));
+        {
+            let execution_budget = Arc::clone(&execution_budget);
+            scheme_vm.register_fn(
+                "set-dynamic-uniform-budget!",
+                move |budget: SteelVal| -> Result<(), String> {
+                    *execution_budget.lock().unwrap() = duration_from_millis(&budget)
+                        .ok_or("Budget should be a positive number of milliseconds")?;
+                    Ok(())
+                },
+            );
+        }
+        {
+            let uniform_status = Arc::clone(&uniform_status);
+            scheme_vm.register_fn("dynamic-uniform-errors", move || {
+                dynamic_uniform_errors(&uniform_status.lock().unwrap())
+            });
+        }
+        {
+            let dynamic_uniforms = Arc::clone(&dynamic_uniforms);
+            let update_rates = Arc::clone(&update_rates);
+            scheme_vm.register_fn(
+                "set-dynamic-uniform-rate!",
+                move |name: String, rate: SteelVal| -> Result<(), String> {
+                    if !dynamic_uniforms.lock().unwrap().contains_key(&name) {
+                        return Err(format!("No dynamic uniform named {}", name));
+                    }
+                    let rate = update_rate_from_steelval(&rate)?;
+                    update_rates.lock().unwrap().insert(name, rate);
+                    Ok(())
+                },
+            );
+        }
+
+        // text drawn on top of the shader, until removed
+        {
+            let output_port = output_port.clone();
+            scheme_vm.register_fn(
+                "draw-text!",
+                move |id: String,
+                      text: SteelVal,
+                      x: SteelVal,
+                      y: SteelVal,
+                      size: SteelVal,
+                      color: SteelVal|
+                      -> Result<(), String> {
+                    let (x, y) = number_from_steelval(&x)
+                        .zip(number_from_steelval(&y))
+                        .ok_or("Text position should be numbers of pixels")?;
+                    let size = number_from_steelval(&size)
+                        .filter(|size| *size > 0.0 && *size <= MAX_TEXT_SIZE as f64)
+                        .ok_or_else(|| {
+                            format!(
+                                "Text size should be a positive number of pixels, up to {}",
+                                MAX_TEXT_SIZE
+                            )
+                        })?;
+                    output_port
+                        .send(RenderCommand::DrawText(
+                            id,
+                            TextElement {
+                                text: display_string(&text),
+                                x: x as f32,
+                                y: y as f32,
+                                size: size as f32,
+                                color: color_from_steelval(&color)?,
+                            },
+                        ))
+                        .unwrap();
+                    Ok(())
+                },
+            );
+        }
+        {
+            let output_port = output_port.clone();
+            scheme_vm.register_fn("remove-text!", move |id: String| {
+                output_port.send(RenderCommand::RemoveText(id)).unwrap();
+            });
+        }
+        {
+            let output_port = output_port.clone();
+            scheme_vm.register_fn("clear-text!", move || {
+                output_port.send(RenderCommand::ClearText).unwrap();
+            });
+        }
 
         // standard library matrix functions
         // TODO: should we support other matrices than 4x4?
@@ -187,32 +619,425 @@ impl NetworkScheme {
         // TODO: a noise function?
         // assume -1.0 to 1.0 domain. Take in size? Should we be able to take in seed as well? or should we var arg this bad boy? Can we have an option last argument? or do everything need to be filled in?
 
-        // get the elapsed time in seconds (floating point)
-        scheme_vm.register_fn("get-elapsed-time", move || {
-            (Instant::now() - start_time).as_secs_f32()
-        });
+        // keyframe animation of uniforms
+        let timeline: Arc<Mutex<Timeline>> = Arc::default();
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn(
+                "add-keyframe!",
+                move |name: String,
+                      time: SteelVal,
+                      value: SteelVal,
+                      curve: String|
+                      -> Result<(), String> {
+                    let time = number_from_steelval(&time)
+                        .ok_or("Keyframe time should be a number of seconds")?;
+                    timeline.lock().unwrap().add_keyframe(
+                        name,
+                        time as f32,
+                        uniform_value_from_steelval(value)?,
+                        Curve::parse(&curve)?,
+                    )
+                },
+            );
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn(
+                "clear-keyframes!",
+                move |name: String| -> Result<(), String> {
+                    if timeline.lock().unwrap().clear_keyframes(&name) {
+                        Ok(())
+                    } else {
+                        Err(format!("No keyframes for {}", name))
+                    }
+                },
+            );
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn("timeline-play!", move || timeline.lock().unwrap().play());
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn("timeline-pause!", move || timeline.lock().unwrap().pause());
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn(
+                "timeline-seek!",
+                move |position: SteelVal| -> Result<(), String> {
+                    let position = number_from_steelval(&position)
+                        .ok_or("Position should be a number of seconds")?;
+                    timeline.lock().unwrap().seek(position as f32);
+                    Ok(())
+                },
+            );
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn("timeline-loop!", move |looping: bool| {
+                timeline.lock().unwrap().set_looping(looping)
+            });
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn(
+                "timeline-speed!",
+                move |speed: SteelVal| -> Result<(), String> {
+                    let speed =
+                        number_from_steelval(&speed).ok_or("Playback speed should be a number")?;
+                    timeline.lock().unwrap().set_speed(speed as f32);
+                    Ok(())
+                },
+            );
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn("timeline-position", move || {
+                timeline.lock().unwrap().position()
+            });
+        }
+        {
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn("timeline-duration", move || {
+                timeline.lock().unwrap().duration()
+            });
+        }
+
+        // frame synchronisation, where the renderer asks for all dynamic uniforms before drawing each frame
+        let frame_sync = Arc::new(Mutex::new(false));
+        let current_frame = Arc::new(Mutex::new((0, 0.0)));
+        {
+            let frame_sync = Arc::clone(&frame_sync);
+            let output_port = output_port.clone();
+            scheme_vm.register_fn("set-frame-sync!", move |enabled: bool| {
+                *frame_sync.lock().unwrap() = enabled;
+                output_port
+                    .send(RenderCommand::SetFrameSync(enabled))
+                    .unwrap();
+            });
+        }
+        {
+            let current_frame = Arc::clone(&current_frame);
+            scheme_vm.register_fn("frame-index", move || {
+                current_frame.lock().unwrap().0 as usize
+            });
+        }
+        {
+            let current_frame = Arc::clone(&current_frame);
+            scheme_vm.register_fn("frame-time", move || current_frame.lock().unwrap().1);
+        }
+
+        // global clock, which everything animated follows. Can be paused and scrubbed to inspect an animation
+        let clock: Arc<Mutex<Clock>> = Arc::default();
+        {
+            let clock = Arc::clone(&clock);
+            scheme_vm.register_fn("pause-time", move || clock.lock().unwrap().pause());
+        }
+        {
+            let clock = Arc::clone(&clock);
+            scheme_vm.register_fn("resume-time", move || clock.lock().unwrap().resume());
+        }
+        {
+            let clock = Arc::clone(&clock);
+            let timeline = Arc::clone(&timeline);
+            scheme_vm.register_fn("set-time!", move |time: SteelVal| -> Result<(), String> {
+                let time =
+                    number_from_steelval(&time).ok_or("Time should be a number of seconds")?;
+                clock.lock().unwrap().set_time(time);
+                timeline.lock().unwrap().reset_clock();
+                Ok(())
+            });
+        }
+        {
+            let clock = Arc::clone(&clock);
+            scheme_vm.register_fn(
+                "set-time-scale!",
+                move |scale: SteelVal| -> Result<(), String> {
+                    let scale =
+                        number_from_steelval(&scale).ok_or("Time scale should be a number")?;
+                    clock.lock().unwrap().set_scale(scale);
+                    Ok(())
+                },
+            );
+        }
+        {
+            let clock = Arc::clone(&clock);
+            scheme_vm.register_fn("time-paused?", move || clock.lock().unwrap().is_paused());
+        }
+
+        // get the elapsed time in seconds (floating point). Follows the frame time when frame synchronised, so animations line up with frames
+        {
+            let frame_sync = Arc::clone(&frame_sync);
+            let current_frame = Arc::clone(&current_frame);
+            let clock = Arc::clone(&clock);
+            scheme_vm.register_fn("get-elapsed-time", move || {
+                if *frame_sync.lock().unwrap() {
+                    current_frame.lock().unwrap().1
+                } else {
+                    clock.lock().unwrap().time()
+                }
+            });
+        }
+
+        // printing goes to a string port of its own while Scheme code runs, see `capture_output`
+        scheme_vm
+            .run(
+                "(define %console-output-port (current-output-port))
+                 (define %captured-output (open-output-string))
+                 (define (%start-capture)
+                   (set! %captured-output (open-output-string))
+                   (current-output-port %captured-output))
+                 (define (%stop-capture)
+                   (current-output-port %console-output-port)
+                   (get-output-string %captured-output))"
+                    .to_string(),
+            )
+            .expect("Could not define output capturing");
+
+        // the size is optional, which is easier to do in Scheme
+        {
+            let output_port = output_port.clone();
+            let has_renderer = Arc::clone(&has_renderer);
+            scheme_vm.register_fn(
+                "%save-screenshot",
+                move |path: String, size: SteelVal| -> Result<(), String> {
+                    let size = size_from_steelval(&size)?;
+                    request_from_renderer(
+                        &output_port,
+                        &has_renderer,
+                        |reply| RenderCommand::SaveScreenshot(PathBuf::from(path), size, reply),
+                        SCREENSHOT_REPLY_TIMEOUT,
+                    )?
+                },
+            );
+        }
+        scheme_vm
+            .run("(define (save-screenshot path . size) (%save-screenshot path size))".to_string())
+            .expect("Could not define save-screenshot");
+
+        // the renderer runs the export and asks for the uniforms of each frame, so this returns once it has started
+        {
+            let output_port = output_port.clone();
+            let has_renderer = Arc::clone(&has_renderer);
+            scheme_vm.register_fn(
+                "%export-frames!",
+                move |path: String,
+                      frames: SteelVal,
+                      fps: SteelVal,
+                      size: SteelVal|
+                      -> Result<(), String> {
+                    let settings = ExportSettings {
+                        path: PathBuf::from(path),
+                        frames: count_from_steelval(&frames)
+                            .ok_or("Frames should be a positive whole number")?,
+                        // checked as f32, as that is what it ends up as
+                        fps: number_from_steelval(&fps)
+                            .map(|fps| fps as f32)
+                            .filter(|fps| FPS_RANGE.contains(fps))
+                            .ok_or_else(|| {
+                                format!(
+                                    "Frames per second should be a number from {} to {}",
+                                    FPS_RANGE.start(),
+                                    FPS_RANGE.end()
+                                )
+                            })?,
+                        size: size_from_steelval(&size)?,
+                    };
+                    request_from_renderer(
+                        &output_port,
+                        &has_renderer,
+                        |reply| RenderCommand::StartExport(settings, reply),
+                        RENDERER_REPLY_TIMEOUT,
+                    )?
+                },
+            );
+        }
+        scheme_vm
+            .run(
+                "(define (export-frames! path frames fps . size) (%export-frames! path frames fps size))"
+                    .to_string(),
+            )
+            .expect("Could not define export-frames!");
 
         // simple texture loading
         scheme_vm.register_type::<Texture>("texture?");
         scheme_vm.register_fn("load-texture", |filename: String| Texture::new(filename));
 
-        // start a background process that listens to updates from renderer
-        // TODO: maybe this setup fits better as a separate method being called in main loop?
-        let render_state = Arc::new(Mutex::new(RenderState::default()));
+        // callbacks for renderer events. Called from the main loop, as the VM can't leave this thread
+        let event_callbacks: EventCallbackTable = Arc::default();
+        {
+            let event_callbacks = Arc::clone(&event_callbacks);
+            scheme_vm.register_fn(
+                "on-event!",
+                move |event: String, callback: SteelVal| -> Result<(), String> {
+                    check_event_name(&event)?;
+                    // otherwise it would fail on every event instead
+                    if !callback.is_function() {
+                        return Err(format!(
+                            "Callback for {} should be a function, called with the arguments of the event",
+                            event
+                        ));
+                    }
+
+                    event_callbacks
+                        .lock()
+                        .unwrap()
+                        .entry(event)
+                        .or_default()
+                        .push(callback);
+                    Ok(())
+                },
+            );
+        }
+        {
+            let event_callbacks = Arc::clone(&event_callbacks);
+            scheme_vm.register_fn(
+                "clear-event-handlers!",
+                move |event: String| -> Result<(), String> {
+                    check_event_name(&event)?;
+                    event_callbacks.lock().unwrap().remove(&event);
+                    Ok(())
+                },
+            );
+        }
+
+        // start a background process that listens to updates from renderer.
+        // Keeps the render state up to date, and forwards the events to the main loop for callbacks.
         let render_state_clone = Arc::clone(&render_state);
+        let (event_sender, event_receiver) = channel();
         thread::spawn(move || {
             let render_state = render_state_clone;
-            loop {
-                if let Ok(command) = input_port.recv() {
-                    match command {
-                        StateUpdateCommand::ScreenSizeChanged(width, height) => {
-                            render_state.lock().unwrap().screen_size = (width, height);
-                        }
+            while let Ok(command) = input_port.recv() {
+                match &command {
+                    StateUpdateCommand::ScreenSizeChanged(width, height) => {
+                        render_state.lock().unwrap().screen_size = (*width, *height);
+                    }
+                    StateUpdateCommand::ShaderReloaded => {
+                        render_state.lock().unwrap().diagnostics.clear();
+                    }
+                    StateUpdateCommand::ShaderCompileFailed(_, diagnostics) => {
+                        render_state.lock().unwrap().diagnostics = diagnostics.clone();
+                    }
+                    StateUpdateCommand::FrameStats(stats) => {
+                        render_state.lock().unwrap().frame_stats = stats.clone();
                     }
+                    StateUpdateCommand::ShaderUniforms(uniforms) => {
+                        render_state.lock().unwrap().shader_uniforms = Some(uniforms.clone());
+                    }
+                    _ => {}
+                }
+
+                // nobody to forward to if the scheme instance is gone
+                if event_sender.send(command).is_err() {
+                    break;
                 }
             }
         });
 
+        // compile errors and warnings as (file line column severity message) lists. Line and column are #false when unknown
+        {
+            let render_state = Arc::clone(&render_state);
+            scheme_vm.register_fn("shader-diagnostics", move || {
+                render_state
+                    .lock()
+                    .unwrap()
+                    .diagnostics
+                    .iter()
+                    .map(diagnostic_to_steelval)
+                    .collect::<Vec<_>>()
+            });
+        }
+
+        // performance of the recent frames as (fps cpu-ms gpu-ms passes), where passes are (name gpu-ms) lists
+        {
+            let render_state = Arc::clone(&render_state);
+            scheme_vm.register_fn("frame-stats", move || {
+                frame_stats_to_steelval(&render_state.lock().unwrap().frame_stats)
+            });
+        }
+        {
+            let output_port = output_port.clone();
+            scheme_vm.register_fn("show-uniform-inspector!", move |enabled: bool| {
+                output_port
+                    .send(RenderCommand::ShowUniformInspector(enabled))
+                    .unwrap();
+            });
+        }
+        {
+            let output_port = output_port.clone();
+            scheme_vm.register_fn("show-frame-stats!", move |enabled: bool| {
+                output_port
+                    .send(RenderCommand::ShowFrameStats(enabled))
+                    .unwrap();
+            });
+        }
+
+        // live state of the renderer, asked for on every call. #false for uniforms that were never set
+        {
+            let output_port = output_port.clone();
+            let has_renderer = Arc::clone(&has_renderer);
+            scheme_vm.register_fn(
+                "get-uniform",
+                move |name: String| -> Result<SteelVal, String> {
+                    match request_snapshot(&output_port, &has_renderer)?
+                        .uniforms
+                        .get(&name)
+                    {
+                        Some(value) => uniform_value_to_steelval(value),
+                        None => Ok(SteelVal::BoolV(false)),
+                    }
+                },
+            );
+        }
+        {
+            let output_port = output_port.clone();
+            let has_renderer = Arc::clone(&has_renderer);
+            scheme_vm.register_fn("list-uniforms", move || -> Result<Vec<String>, String> {
+                Ok(request_snapshot(&output_port, &has_renderer)?
+                    .uniforms
+                    .into_keys()
+                    .collect())
+            });
+        }
+        // (name type) pairs of the uniforms the shader uses, with GLSL type names
+        {
+            let output_port = output_port.clone();
+            let has_renderer = Arc::clone(&has_renderer);
+            scheme_vm.register_fn(
+                "shader-uniforms",
+                move || -> Result<Vec<Vec<String>>, String> {
+                    Ok(request_snapshot(&output_port, &has_renderer)?
+                        .shader_uniforms
+                        .into_iter()
+                        .map(|(name, ty)| vec![name, ty])
+                        .collect())
+                },
+            );
+        }
+        // (name width height) of each texture
+        {
+            let output_port = output_port.clone();
+            let has_renderer = Arc::clone(&has_renderer);
+            scheme_vm.register_fn(
+                "list-textures",
+                move || -> Result<Vec<Vec<SteelVal>>, String> {
+                    Ok(request_snapshot(&output_port, &has_renderer)?
+                        .textures
+                        .into_iter()
+                        .map(|(name, (width, height))| {
+                            vec![
+                                name.into(),
+                                SteelVal::IntV(width as isize),
+                                SteelVal::IntV(height as isize),
+                            ]
+                        })
+                        .collect())
+                },
+            );
+        }
+
         // function to fetch screen size information
         scheme_vm.register_fn("screen-size", move || {
             render_state.lock().unwrap().screen_size
@@ -221,13 +1046,46 @@ impl NetworkScheme {
         Self {
             scheme_vm,
             prev_was_error: false,
+            events: event_receiver,
+            event_callbacks,
+            update_interval,
+            update_rates,
+            last_updates,
+            dynamic_uniforms,
+            execution_budget,
+            watchdog: Watchdog::new(interrupted),
+            has_renderer,
+            uniform_status,
+            frame_sync,
+            current_frame,
+            render_commands: output_port,
+            timeline,
+            clock,
+            output,
+            render_state,
+            clock_before_export: None,
+            unknown_uniform_warnings: HashSet::new(),
+            repl_clients: Arc::default(),
+            repl_host: DEFAULT_REPL_HOST.to_string(),
+            repl_port: DEFAULT_REPL_PORT,
+            repl_socket: None,
+            repl_protocol: Protocol::default(),
         }
     }
 
-    /// Evaluates a scheme expression and returns the return value as a String.
-    // TODO: handle stdout somehow!
+    /// Evaluates a scheme expression and returns the return value as a String, formatted like in the text protocol.
     fn eval(&mut self, expression: String) -> String {
-        let return_value = self.scheme_vm.run(expression);
+        Protocol::Text.encode_reply(&Value::Null, &self.evaluate(expression))
+    }
+
+    /// Evaluates a scheme expression, and gives the printed return value or error, together with what it printed.
+    fn evaluate(&mut self, expression: String) -> EvalResult {
+        // output from before belongs to someone else
+        self.broadcast_output();
+        let return_value = capture_output(&mut self.scheme_vm, &self.output, |scheme_vm| {
+            scheme_vm.run(expression)
+        });
+        let stdout = self.take_output();
 
         match return_value {
             Ok(return_value) => {
@@ -238,16 +1096,9 @@ impl NetworkScheme {
                 // Void is also a SteelVal type :)
                 let result = return_value.last().unwrap_or(&SteelVal::Void);
 
-                // Ugly hack to use our own Display implementations for custom types
-                // TODO: refactor with match
-                if let SteelVal::Custom(val) = result {
-                    if let Some(matrix) = val.borrow().as_any_ref().downcast_ref::<Matrix>() {
-                        format!("{}\n", matrix)
-                    } else {
-                        format!("{}\n", result)
-                    }
-                } else {
-                    format!("{}\n", result)
+                EvalResult {
+                    value: Ok(value_to_string(result)),
+                    stdout,
                 }
             }
             Err(err) => {
@@ -255,42 +1106,866 @@ impl NetworkScheme {
                 // prints the error to console for debugging purposes
                 eprintln!("[ERROR] {}", err);
 
-                format!("ERROR: Evaluation failed: {}\n", err)
+                EvalResult {
+                    value: Err(err.to_string()),
+                    stdout,
+                }
             }
         }
     }
 
-    /// Runs one iteration of dynamic updates. Meant to be used in a loop and called regularly.
-    fn run_dynamic_updates(&mut self) -> Result<(), String> {
-        let dynamic_uniform_table = self
-            .scheme_vm
-            .extract::<HashMap<String, SteelVal>>("DYNAMIC_UNIFORM_TABLE")
-            .expect("table should always exist");
+    /// Runs the registered callbacks for all events received from the renderer since last call.
+    fn dispatch_events(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                StateUpdateCommand::FrameTick(frame) => {
+                    self.run_frame_sync(frame, self.time());
+                }
+                StateUpdateCommand::FrameRendered(..)
+                    if !self.is_frame_synced() && !self.is_exporting() =>
+                {
+                    self.run_frame_updates();
+                    self.run_timeline(self.time());
+                }
+                StateUpdateCommand::TimeControl(control) => self.control_time(control),
+                StateUpdateCommand::ShaderReloaded => {
+                    self.notify_clients(&Notification::ShaderReloaded);
+                }
+                StateUpdateCommand::ShaderCompileFailed(ref log, ref diagnostics) => {
+                    self.notify_clients(&Notification::ShaderCompileFailed(
+                        log.clone(),
+                        diagnostics.clone(),
+                    ));
+                }
+                // names may have come into use, or gone out of it
+                StateUpdateCommand::ShaderUniforms(_) => self.unknown_uniform_warnings.clear(),
+                StateUpdateCommand::ExportFrame(frame, index, time) => {
+                    self.run_export_frame(frame, index, time)
+                }
+                StateUpdateCommand::ExportFinished(ref result) => self.finish_export(result),
+                StateUpdateCommand::ScreenshotSaved(ref result) => match result {
+                    Ok(message) => self.broadcast(&format!("[INFO] {}", message)),
+                    Err(err) => self.broadcast(&format!("[ERROR] {}", err)),
+                },
+                _ => {}
+            }
 
-        for (name, val) in &dynamic_uniform_table {
-            if let SteelVal::Closure(_) = val {
-                // TODO: better error handling here.. Lots of internal methods used. Electric bogaloo
-                let result = self
-                    .scheme_vm
-                    .call_function_with_args(val.clone(), vec![])
-                    .unwrap();
-                self.scheme_vm
-                    .call_function_by_name_with_args(
-                        "set-uniform!",
-                        vec![name.clone().into_steelval().unwrap(), result],
-                    )
-                    .unwrap();
-            } else {
-                return Err(
-                    "Second argument to set-dynamic-uniform! should be a lambda.".to_string(),
-                );
+            self.run_event_callbacks(&event);
+        }
+    }
+
+    /// Applies a clock change from the renderer's keyboard shortcuts.
+    fn control_time(&mut self, control: TimeControl) {
+        let mut clock = self.clock.lock().unwrap();
+        match control {
+            TimeControl::TogglePause if clock.is_paused() => clock.resume(),
+            TimeControl::TogglePause => clock.pause(),
+            TimeControl::Skip(seconds) => {
+                clock.skip(seconds as f64);
+                self.timeline.lock().unwrap().reset_clock();
+            }
+            TimeControl::ScaleBy(factor) => {
+                let scale = clock.scale() * factor as f64;
+                clock.set_scale(scale);
+            }
+            TimeControl::Reset => {
+                clock.set_time(0.0);
+                self.timeline.lock().unwrap().reset_clock();
+            }
+        }
+    }
+
+    /// Runs all callbacks registered for the given event. A failing callback is reported, but does not stop the others.
+    fn run_event_callbacks(&mut self, event: &StateUpdateCommand) {
+        let Some((name, args)) = event_callback_args(event) else {
+            return;
+        };
+
+        // cloned to not hold the lock while running callbacks, as they may register new callbacks
+        let callbacks = self
+            .event_callbacks
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+        for callback in callbacks {
+            if let Err(err) = capture_output(&mut self.scheme_vm, &self.output, |scheme_vm| {
+                scheme_vm.call_function_with_args(callback, args.clone())
+            }) {
+                eprintln!("[ERROR] {} callback failed: {}", name, err);
+            }
+        }
+    }
+
+    /// Runs one iteration of dynamic updates, recalculating the dynamic uniforms that are due. Meant to be used in a loop and called regularly.
+    fn run_dynamic_updates(&mut self) {
+        let now = Instant::now();
+        for (name, val) in self.dynamic_uniforms() {
+            if self.is_update_due(&name, now) {
+                self.update_dynamic_uniform(name, val);
+            }
+        }
+    }
+
+    /// Evaluates the dynamic uniforms for a frame tick, and sends them to the renderer as one batch to be applied before the frame is drawn.
+    /// Uniforms following the default rate are updated every frame.
+    fn run_frame_sync(&mut self, frame: u64, time: f32) {
+        self.send_frame_uniforms(frame, time, false);
+    }
+
+    /// Stops the clock at the time of an exported frame, and sends the uniforms of the frame. All dynamic uniforms are
+    /// evaluated, whatever their rate, so the frame does not depend on how long the previous ones took to render. The
+    /// export starts at the first frame, so frames still arriving after it finished are left alone.
+    fn run_export_frame(&mut self, frame: u64, index: u32, time: f32) {
+        if index > 0 && !self.is_exporting() {
+            // answered without uniforms, so the renderer doesn't wait for them
+            self.render_commands
+                .send(RenderCommand::UniformBatch(frame, Vec::new()))
+                .unwrap();
+            return;
+        }
+        {
+            let mut clock = self.clock.lock().unwrap();
+            if self.clock_before_export.is_none() {
+                self.clock_before_export = Some((clock.time(), clock.is_paused()));
+                // the timeline carries on from where it is, rather than jumping by the time the export skips
+                self.timeline.lock().unwrap().reset_clock();
+            }
+            clock.pause();
+            clock.set_time(time as f64);
+        }
+        self.send_frame_uniforms(frame, time, true);
+    }
+
+    /// Runs the setup script and sends the uniforms of a single frame at the given time, without the REPL. For rendering an
+    /// image without a window, where there is no renderer to tell the screen size and shader uniforms, so they are given.
+    /// Asking the renderer for anything fails right away.
+    #[cfg(not(target_vendor = "apple"))]
+    pub fn run_single_frame(
+        &mut self,
+        screen_size: (u32, u32),
+        shader_uniforms: DeclaredUniforms,
+        script: Option<String>,
+        time: f32,
+    ) -> Result<(), String> {
+        {
+            let mut render_state = self.render_state.lock().unwrap();
+            render_state.screen_size = screen_size;
+            render_state.shader_uniforms = Some(shader_uniforms);
+        }
+        self.has_renderer.store(false, Ordering::Relaxed);
+        self.stop_clock_at(time);
+        if let Some(script) = script {
+            let result = capture_output(&mut self.scheme_vm, &self.output, |scheme_vm| {
+                scheme_vm.run(script)
+            });
+            print!("{}", self.take_output());
+            result.map_err(|err| format!("Setup script failed: {}", err))?;
+        }
+        // the script may have moved the clock
+        self.stop_clock_at(time);
+        self.send_frame_uniforms(0, time, true);
+        Ok(())
+    }
+
+    #[cfg(not(target_vendor = "apple"))]
+    fn stop_clock_at(&self, time: f32) {
+        let mut clock = self.clock.lock().unwrap();
+        clock.pause();
+        clock.set_time(time as f64);
+        self.timeline.lock().unwrap().reset_clock();
+    }
+
+    /// Gives the clock back after an export, and tells the REPL clients how it went.
+    fn finish_export(&mut self, result: &Result<String, String>) {
+        if let Some((time, paused)) = self.clock_before_export.take() {
+            let mut clock = self.clock.lock().unwrap();
+            clock.set_time(time as f64);
+            if !paused {
+                clock.resume();
+            }
+            self.timeline.lock().unwrap().reset_clock();
+        }
+        match result {
+            Ok(message) => self.broadcast(&format!("[INFO] {}", message)),
+            Err(err) => self.broadcast(&format!("[ERROR] Export failed: {}", err)),
+        }
+    }
+
+    /// Evaluates the dynamic uniforms that are due, or all of them, and sends them to the renderer together with the
+    /// timeline as the batch for the frame.
+    fn send_frame_uniforms(&mut self, frame: u64, time: f32, evaluate_all: bool) {
+        *self.current_frame.lock().unwrap() = (frame, time);
+
+        let now = Instant::now();
+        let mut batch = Vec::new();
+        for (name, val) in self.dynamic_uniforms() {
+            let is_due = evaluate_all
+                || match self.update_rate(&name) {
+                    UpdateRate::Default | UpdateRate::EveryFrame => true,
+                    UpdateRate::Interval(_) => self.is_update_due(&name, now),
+                };
+
+            if is_due && let Some(value) = self.evaluate_dynamic_uniform(&name, val) {
+                batch.push((name, value));
+            }
+        }
+        if let Some(samples) = self.timeline.lock().unwrap().update(time) {
+            batch.extend(samples);
+        }
+
+        self.render_commands
+            .send(RenderCommand::UniformBatch(frame, batch))
+            .unwrap();
+    }
+
+    /// Moves the timeline forward to the given time, and sends the animated uniforms to the renderer if there is anything new.
+    fn run_timeline(&mut self, time: f32) {
+        let samples = self.timeline.lock().unwrap().update(time);
+        if let Some(samples) = samples {
+            let frame = self.current_frame.lock().unwrap().0;
+            self.render_commands
+                .send(RenderCommand::UniformBatch(frame, samples))
+                .unwrap();
+        }
+    }
+
+    /// Recalculates the dynamic uniforms that should be updated once per rendered frame.
+    fn run_frame_updates(&mut self) {
+        for (name, val) in self.dynamic_uniforms() {
+            if self.update_rate(&name) == UpdateRate::EveryFrame {
+                self.update_dynamic_uniform(name, val);
+            }
+        }
+    }
+
+    /// Copy of the dynamic uniforms, so they can be updated while the Scheme functions change the table.
+    fn dynamic_uniforms(&self) -> HashMap<String, SteelVal> {
+        self.dynamic_uniforms.lock().unwrap().clone()
+    }
+
+    fn update_rate(&self, name: &str) -> UpdateRate {
+        self.update_rates
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or(UpdateRate::Default)
+    }
+
+    /// Whether enough time has passed since the dynamic uniform was last updated. Uniforms updated every frame are never due here.
+    fn is_update_due(&self, name: &str, now: Instant) -> bool {
+        let interval = match self.update_rate(name) {
+            UpdateRate::Default => *self.update_interval.lock().unwrap(),
+            UpdateRate::Interval(interval) => interval,
+            UpdateRate::EveryFrame => return false,
+        };
+
+        self.last_updates
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_none_or(|last_update| now - *last_update >= interval)
+    }
+
+    /// Calls the function of a dynamic uniform, and sends the result to the renderer.
+    fn update_dynamic_uniform(&mut self, name: String, val: SteelVal) {
+        if let Some(value) = self.evaluate_dynamic_uniform(&name, val) {
+            self.render_commands
+                .send(RenderCommand::SetUniform(name, value))
+                .unwrap();
+        }
+    }
+
+    /// Calls the function of a dynamic uniform within its execution budget, and converts the result to a uniform value.
+    /// Gives None if it failed. A function still running when the budget is up is interrupted. A uniform that keeps failing
+    /// or running over budget is disabled.
+    fn evaluate_dynamic_uniform(&mut self, name: &str, val: SteelVal) -> Option<UniformValue> {
+        let budget = *self.execution_budget.lock().unwrap();
+        let start = Instant::now();
+        let result = self.call_dynamic_uniform(name, val, budget);
+        let elapsed = start.elapsed();
+        self.last_updates
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), Instant::now());
+
+        let (prev_error, errors, overruns) = {
+            let mut uniform_status = self.uniform_status.lock().unwrap();
+            let status = uniform_status.entry(name.to_string()).or_default();
+            let prev_error = status.last_error.clone();
+            match &result {
+                Ok(_) => {
+                    status.errors = 0;
+                    status.last_error = None;
+                    if elapsed > budget {
+                        status.overruns += 1;
+                    } else {
+                        status.overruns = 0;
+                    }
+                }
+                // e.g, interrupted when the budget was up
+                Err(err) if elapsed > budget => {
+                    status.overruns += 1;
+                    status.last_error = Some(err.clone());
+                }
+                Err(err) => {
+                    status.errors += 1;
+                    status.last_error = Some(err.clone());
+                }
+            }
+
+            (prev_error, status.errors, status.overruns)
+        };
+
+        if errors >= MAX_STRIKES {
+            let err = result.as_ref().err().cloned().unwrap_or_default();
+            self.disable_dynamic_uniform(
+                name,
+                &format!("failed {} times in a row. Last error: {}", MAX_STRIKES, err),
+            );
+        } else if overruns >= MAX_STRIKES {
+            self.disable_dynamic_uniform(
+                name,
+                &format!(
+                    "took {:.2} ms, over the budget of {:.2} ms {} times in a row",
+                    elapsed.as_secs_f64() * 1000.0,
+                    budget.as_secs_f64() * 1000.0,
+                    MAX_STRIKES
+                ),
+            );
+        }
+
+        // only bother the renderer when the errors change
+        let last_error = self
+            .uniform_status
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|status| status.last_error.clone());
+        if prev_error != last_error {
+            self.render_commands
+                .send(RenderCommand::SetUniformErrors(dynamic_uniform_errors(
+                    &self.uniform_status.lock().unwrap(),
+                )))
+                .unwrap();
+        }
+
+        result
+            .inspect_err(|err| eprintln!("[ERROR] Dynamic uniform {} failed: {}", name, err))
+            .ok()
+    }
+
+    /// Calls the function of a dynamic uniform, interrupting it if it is still running when the budget is up.
+    fn call_dynamic_uniform(
+        &mut self,
+        name: &str,
+        val: SteelVal,
+        budget: Duration,
+    ) -> Result<UniformValue, String> {
+        let watchdog = &self.watchdog;
+        let (result, interrupted) =
+            capture_output(&mut self.scheme_vm, &self.output, |scheme_vm| {
+                watchdog.arm(budget);
+                // functions are validated when set, so we know they take no arguments
+                let result = scheme_vm.call_function_with_args(val, vec![]);
+                // before anything else runs in the VM, as it would be interrupted too
+                (result, watchdog.disarm())
+            });
+        if interrupted {
+            return Err(format!(
+                "interrupted after running over the budget of {:.2} ms",
+                budget.as_secs_f64() * 1000.0
+            ));
+        }
+        let result = result.map_err(|err| err.to_string())?;
+
+        let value = uniform_value_from_steelval(result)?;
+        if let Some(warning) = check_against_shader(&self.render_state, name, &value)?
+            && self.unknown_uniform_warnings.insert(name.to_string())
+        {
+            self.broadcast(&format!("[WARNING] {}", warning));
+        }
+        Ok(value)
+    }
+
+    /// Stops updating a misbehaving dynamic uniform, and lets the REPL clients know. Setting it again enables it.
+    fn disable_dynamic_uniform(&mut self, name: &str, reason: &str) {
+        self.dynamic_uniforms.lock().unwrap().remove(name);
+        // set again, it starts over with the default rate
+        self.update_rates.lock().unwrap().remove(name);
+        self.last_updates.lock().unwrap().remove(name);
+
+        // keep the reason around, so it can be found later
+        let reason = format!("disabled, {}", reason);
+        self.uniform_status.lock().unwrap().insert(
+            name.to_string(),
+            DynamicUniformStatus {
+                last_error: Some(reason.clone()),
+                ..Default::default()
+            },
+        );
+
+        self.broadcast(&format!("[WARNING] Dynamic uniform {} {}", name, reason));
+    }
+}
+
+/// Binds a Unix domain socket only the current user can connect to. The socket is made in a new directory only the user
+/// can enter, and moved into place once its permissions are set, so nobody can connect in between.
+#[cfg(unix)]
+fn bind_private_unix_socket(
+    path: &std::path::Path,
+) -> std::io::Result<std::os::unix::net::UnixListener> {
+    use std::{
+        fs::{self, DirBuilder},
+        os::unix::{
+            fs::{DirBuilderExt, PermissionsExt},
+            net::UnixListener,
+        },
+    };
+
+    let file_name = path.file_name().ok_or(std::io::ErrorKind::InvalidInput)?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+    DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let private_path = private_dir.join(file_name);
+    let result = UnixListener::bind(&private_path).and_then(|listener| {
+        fs::set_permissions(&private_path, fs::Permissions::from_mode(0o600))?;
+        // clients connect through the file, so the listener follows it. Fails like binding would if the path is taken
+        fs::hard_link(&private_path, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&private_path);
+    let _ = fs::remove_dir(&private_dir);
+    result
+}
+
+/// Registers a newly connected REPL client, and runs its read-eval-print loop in a new thread. The client thread only does IO. Evaluation happens in the main loop, which gets the code over a channel.
+fn spawn_repl_client(
+    repl_clients: &Mutex<Vec<ReplClient>>,
+    protocol: Protocol,
+    reader: impl Read + Send + 'static,
+    writer: impl Write + Send + 'static,
+) {
+    // create a command port to send to the repl
+    let (repl_input_sender, repl_input_receiver) = channel();
+    let (repl_output_sender, repl_output_receiver) = channel();
+    let writer: ReplWriter = Arc::new(Mutex::new(Box::new(writer)));
+
+    // Add our own communication channels to the REPLs list of users
+    repl_clients.lock().unwrap().push(ReplClient {
+        input: repl_input_receiver,
+        output: repl_output_sender,
+        writer: Arc::clone(&writer),
+        protocol,
+    });
+
+    let mut reader = BufReader::new(reader);
+
+    // run the repl in a new thread, and let it crash if the user disconnects unexpectedly.
+    thread::spawn(move || {
+        'requests: loop {
+            // write prompt
+            if let Some(prompt) = protocol.prompt() {
+                writer.lock().unwrap().write_all(prompt.as_bytes()).unwrap();
+            }
+
+            // read until the request is complete, which may take several lines
+            let mut buffer = String::new();
+            loop {
+                // the client hung up
+                if let Ok(0) | Err(_) = reader.read_line(&mut buffer) {
+                    break 'requests;
+                }
+
+                match protocol.continuation_prompt() {
+                    Some(prompt) if !is_complete_expression(&buffer) => {
+                        writer.lock().unwrap().write_all(prompt.as_bytes()).unwrap();
+                    }
+                    _ => break,
+                }
             }
+
+            // run the command. Malformed requests are answered right away
+            let reply = match protocol.decode_request(&buffer) {
+                Ok(request) => {
+                    repl_input_sender.send(request.code).unwrap();
+                    let result = repl_output_receiver.recv().unwrap();
+                    protocol.encode_reply(&request.id, &result)
+                }
+                Err(err) => protocol.encode_reply(
+                    &Value::Null,
+                    &EvalResult {
+                        value: Err(err),
+                        stdout: String::new(),
+                    },
+                ),
+            };
+
+            let mut stream = writer.lock().unwrap();
+            stream.write_all(reply.as_bytes()).unwrap();
+
+            stream.flush().unwrap();
         }
+    });
+}
+
+/// Checks that a dynamic uniform function can be called without arguments. Only lambdas tell how many arguments they
+/// take, so built-in functions like `get-elapsed-time` are taken as they are.
+fn validate_dynamic_uniform_function(func: &SteelVal) -> Result<(), String> {
+    match func {
+        SteelVal::Closure(lambda) if lambda.arity() != 0 => Err(format!(
+            "Dynamic uniform functions should take no arguments, but this one takes {}",
+            lambda.arity()
+        )),
+        func if func.is_function() => Ok(()),
+        _ => Err(
+            "Second argument to set-dynamic-uniform! should be a function taking no arguments"
+                .to_string(),
+        ),
+    }
+}
 
+/// Runs Scheme code with the current output port set to a string port of its own, and adds what it printed to `output`.
+/// The printing functions are left alone, so output to other ports works as usual.
+fn capture_output<T>(
+    scheme_vm: &mut Engine,
+    output: &Mutex<String>,
+    run: impl FnOnce(&mut Engine) -> T,
+) -> T {
+    // printing to the console is better than not printing at all, so a failure here is not fatal
+    let _ = scheme_vm.call_function_by_name_with_args("%start-capture", vec![]);
+    let result = run(scheme_vm);
+    if let Ok(SteelVal::StringV(printed)) =
+        scheme_vm.call_function_by_name_with_args("%stop-capture", vec![])
+    {
+        output.lock().unwrap().push_str(printed.as_str());
+    }
+    result
+}
+
+/// Checks that callbacks can be registered for the event.
+fn check_event_name(event: &str) -> Result<(), String> {
+    if EVENT_NAMES.contains(&event) {
         Ok(())
+    } else {
+        Err(format!(
+            "Unknown event {}. Available events: {}",
+            event,
+            EVENT_NAMES.join(", ")
+        ))
+    }
+}
+
+/// Forgets the status of a dynamic uniform, and updates the renderer if it had an error.
+fn clear_dynamic_uniform_status(
+    uniform_status: &Mutex<HashMap<String, DynamicUniformStatus>>,
+    name: &str,
+    output_port: &Sender<RenderCommand>,
+) {
+    let mut uniform_status = uniform_status.lock().unwrap();
+    if uniform_status
+        .remove(name)
+        .is_some_and(|status| status.last_error.is_some())
+    {
+        output_port
+            .send(RenderCommand::SetUniformErrors(dynamic_uniform_errors(
+                &uniform_status,
+            )))
+            .unwrap();
+    }
+}
+
+/// Name and last error of each failing dynamic uniform, sorted by name.
+fn dynamic_uniform_errors(
+    uniform_status: &HashMap<String, DynamicUniformStatus>,
+) -> Vec<(String, String)> {
+    let mut errors: Vec<(String, String)> = uniform_status
+        .iter()
+        .filter_map(|(name, status)| Some((name.clone(), status.last_error.clone()?)))
+        .collect();
+    errors.sort();
+    errors
+}
+
+/// Compares a uniform value with what the shader in use declares. A value of the wrong type is an error, as the renderer
+/// can't use it. An unknown name only gives a warning, as the shader may be about to change.
+fn check_against_shader(
+    render_state: &Mutex<RenderState>,
+    name: &str,
+    value: &UniformValue,
+) -> Result<Option<String>, String> {
+    let render_state = render_state.lock().unwrap();
+    let Some(declared) = &render_state.shader_uniforms else {
+        return Ok(None);
+    };
+    match check_uniform(name, value.glsl_type(), declared) {
+        Some(problem @ UniformProblem::WrongType(..)) => Err(problem.message(name)),
+        Some(problem) => Ok(Some(problem.message(name))),
+        None => Ok(None),
+    }
+}
+
+/// Converts a Scheme value to a uniform value, inferring the GLSL type from the Scheme type.
+fn uniform_value_from_steelval(value: SteelVal) -> Result<UniformValue, String> {
+    // TODO: better error handling!
+    match value {
+        SteelVal::NumV(num) => Ok(UniformValue::Float(num as f32)),
+        SteelVal::ListV(list) => {
+            // TODO: make prettier. This is fucking ugly
+            let all_nums: Vec<Option<f32>> = list
+                .iter()
+                .map(|elem| match elem {
+                    SteelVal::NumV(num) => Some(*num as f32),
+                    _ => None,
+                })
+                .collect();
+            if all_nums.len() == 3 && all_nums.iter().all(|elem| elem.is_some()) {
+                Ok(UniformValue::Vector3(
+                    all_nums[0].unwrap(),
+                    all_nums[1].unwrap(),
+                    all_nums[2].unwrap(),
+                ))
+            } else {
+                Err("uniform only support coercing 3 element lists to vec3".to_string())
+            }
+        }
+        SteelVal::Custom(val) => {
+            if let Some(matrix) = val.borrow().as_any_ref().downcast_ref::<Matrix>() {
+                Ok(UniformValue::Matrix(matrix.into()))
+            } else if let Some(texture) = val.borrow().as_any_ref().downcast_ref::<Texture>() {
+                Ok(UniformValue::RgbaTexture2D(texture.image.clone()))
+            } else {
+                Err("Unsupported custom type for uniforms".to_string())
+            }
+        }
+        _ => Err("thats a paddlin".to_string()),
+    }
+}
+
+/// Converts a uniform value back to the Scheme value that would set it.
+fn uniform_value_to_steelval(value: &UniformValue) -> Result<SteelVal, String> {
+    match value {
+        UniformValue::Float(num) => Ok(SteelVal::NumV(*num as f64)),
+        UniformValue::Vector3(x, y, z) => Ok(vec![*x as f64, *y as f64, *z as f64]
+            .into_steelval()
+            .unwrap()),
+        UniformValue::Matrix(matrix) => Ok(Matrix::from(matrix).into_steelval().unwrap()),
+        UniformValue::RgbaTexture2D(_) => Err("Textures can't be read back".to_string()),
+    }
+}
+
+/// Sends a request to the renderer, and waits for the answer on a channel of its own. Fails right away if there is no
+/// renderer to answer it.
+fn request_from_renderer<T>(
+    render_commands: &Sender<RenderCommand>,
+    has_renderer: &AtomicBool,
+    request: impl FnOnce(Reply<T>) -> RenderCommand,
+    timeout: Duration,
+) -> Result<T, String> {
+    if !has_renderer.load(Ordering::Relaxed) {
+        return Err("There is no renderer to ask without a window".to_string());
+    }
+    let (sender, receiver) = channel();
+    render_commands.send(request(Reply(sender))).unwrap();
+    receiver
+        .recv_timeout(timeout)
+        .map_err(|_| "The renderer did not answer in time".to_string())
+}
+
+/// Asks the renderer for its current uniforms and textures.
+fn request_snapshot(
+    render_commands: &Sender<RenderCommand>,
+    has_renderer: &AtomicBool,
+) -> Result<RenderSnapshot, String> {
+    request_from_renderer(
+        render_commands,
+        has_renderer,
+        RenderCommand::GetSnapshot,
+        RENDERER_REPLY_TIMEOUT,
+    )
+}
+
+/// Parses an optional width and height, given as a list of the rest arguments. None when left out, for the size of the
+/// window.
+fn size_from_steelval(size: &SteelVal) -> Result<Option<(u32, u32)>, String> {
+    let dimensions: Option<Vec<u32>> = match size {
+        SteelVal::ListV(list) => list.iter().map(count_from_steelval).collect(),
+        _ => None,
+    };
+    match dimensions.as_deref() {
+        Some([]) => Ok(None),
+        Some([width, height]) => Ok(Some((*width, *height))),
+        _ => Err("Size should be a width and a height in whole pixels".to_string()),
+    }
+}
+
+/// Printed representation of a value, like the REPL shows it.
+fn value_to_string(value: &SteelVal) -> String {
+    // Ugly hack to use our own Display implementations for custom types
+    if let SteelVal::Custom(val) = value
+        && let Some(matrix) = val.borrow().as_any_ref().downcast_ref::<Matrix>()
+    {
+        matrix.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Text `display` prints for a value. Strings and characters are printed as they are, without quotes.
+fn display_string(value: &SteelVal) -> String {
+    match value {
+        SteelVal::StringV(string) => string.as_str().to_string(),
+        SteelVal::CharV(character) => character.to_string(),
+        value => value_to_string(value),
+    }
+}
+
+/// A diagnostic as a Scheme list of file, line, column, severity and message.
+fn diagnostic_to_steelval(diagnostic: &Diagnostic) -> Vec<SteelVal> {
+    let number_or_false = |number: Option<u32>| {
+        number.map_or(SteelVal::BoolV(false), |number| {
+            SteelVal::IntV(number as isize)
+        })
+    };
+    vec![
+        SteelVal::StringV(diagnostic.file.clone().into()),
+        number_or_false(diagnostic.line),
+        number_or_false(diagnostic.column),
+        SteelVal::StringV(diagnostic.severity.to_string().into()),
+        SteelVal::StringV(diagnostic.message.clone().into()),
+    ]
+}
+
+/// Converts a list of red, green and blue from 0.0 to 1.0, like vec3 uniforms, to a color.
+fn color_from_steelval(color: &SteelVal) -> Result<(u8, u8, u8), String> {
+    let components: Vec<u8> = match color {
+        SteelVal::ListV(list) => list
+            .iter()
+            .map(|component| {
+                number_from_steelval(component).map(|number| (number.clamp(0.0, 1.0) * 255.0) as u8)
+            })
+            .collect::<Option<_>>()
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    match components[..] {
+        [r, g, b] => Ok((r, g, b)),
+        _ => Err("Color should be a list of red, green and blue from 0.0 to 1.0".to_string()),
+    }
+}
+
+/// Frame statistics as a Scheme list of FPS, CPU time, GPU time and GPU time per pass. Times are in milliseconds,
+/// and the GPU time is #false when not measured.
+fn frame_stats_to_steelval(stats: &FrameStatsSummary) -> Vec<SteelVal> {
+    let passes: Vec<Vec<SteelVal>> = stats
+        .passes
+        .iter()
+        .map(|(pass, time)| {
+            vec![
+                SteelVal::StringV(pass.clone().into()),
+                SteelVal::NumV(millis(*time)),
+            ]
+        })
+        .collect();
+    vec![
+        SteelVal::NumV(stats.fps as f64),
+        SteelVal::NumV(millis(stats.cpu_time)),
+        stats
+            .gpu_time
+            .map_or(SteelVal::BoolV(false), |time| SteelVal::NumV(millis(time))),
+        passes.into_steelval().unwrap(),
+    ]
+}
+
+/// Scheme numbers can be both integers and floats. We don't care which.
+fn number_from_steelval(number: &SteelVal) -> Option<f64> {
+    match number {
+        SteelVal::IntV(number) => Some(*number as f64),
+        SteelVal::NumV(number) => Some(*number),
+        _ => None,
+    }
+}
+
+/// Converts a Scheme number to a positive whole number, e.g, a number of pixels.
+fn count_from_steelval(number: &SteelVal) -> Option<u32> {
+    number_from_steelval(number)
+        .filter(|number| number.fract() == 0.0 && (1.0..=u32::MAX as f64).contains(number))
+        .map(|number| number as u32)
+}
+
+/// Converts a Scheme number of milliseconds to a duration. Only positive numbers are valid.
+fn duration_from_millis(millis: &SteelVal) -> Option<Duration> {
+    number_from_steelval(millis)
+        .filter(|millis| *millis > 0.0)
+        .map(|millis| Duration::from_secs_f64(millis / 1000.0))
+}
+
+/// Parses the rate argument of `set-dynamic-uniform-rate!`.
+fn update_rate_from_steelval(rate: &SteelVal) -> Result<UpdateRate, String> {
+    match rate {
+        SteelVal::StringV(mode) if mode.as_str() == "frame" => Ok(UpdateRate::EveryFrame),
+        SteelVal::StringV(mode) if mode.as_str() == "default" => Ok(UpdateRate::Default),
+        millis => duration_from_millis(millis)
+            .map(UpdateRate::Interval)
+            .ok_or_else(|| {
+                "Rate should be \"frame\", \"default\" or a positive number of milliseconds"
+                    .to_string()
+            }),
     }
 }
 
+/// Event name and callback arguments for a renderer event. Internal events not meant for callbacks gives None.
+fn event_callback_args(event: &StateUpdateCommand) -> Option<(&'static str, Vec<SteelVal>)> {
+    let args = match event {
+        StateUpdateCommand::ScreenSizeChanged(width, height) => (
+            "resize",
+            vec![
+                SteelVal::IntV(*width as isize),
+                SteelVal::IntV(*height as isize),
+            ],
+        ),
+        StateUpdateCommand::ShaderReloaded => ("reload", vec![]),
+        StateUpdateCommand::ShaderCompileFailed(error, _) => (
+            "compile-error",
+            vec![error.clone().into_steelval().unwrap()],
+        ),
+        StateUpdateCommand::KeyPressed(key) => {
+            ("key-press", vec![key.clone().into_steelval().unwrap()])
+        }
+        StateUpdateCommand::KeyReleased(key) => {
+            ("key-release", vec![key.clone().into_steelval().unwrap()])
+        }
+        StateUpdateCommand::MouseMoved(x, y) => (
+            "mouse-move",
+            vec![SteelVal::NumV(*x as f64), SteelVal::NumV(*y as f64)],
+        ),
+        StateUpdateCommand::MouseClicked(button) => {
+            ("mouse-click", vec![button.clone().into_steelval().unwrap()])
+        }
+        StateUpdateCommand::FrameRendered(frame, frame_time) => (
+            "frame",
+            vec![
+                SteelVal::IntV(*frame as isize),
+                SteelVal::NumV(*frame_time as f64),
+            ],
+        ),
+        StateUpdateCommand::FrameTick(..)
+        | StateUpdateCommand::TimeControl(..)
+        | StateUpdateCommand::FrameStats(..)
+        | StateUpdateCommand::ShaderUniforms(..)
+        | StateUpdateCommand::ExportFrame(..)
+        | StateUpdateCommand::ExportFinished(..)
+        | StateUpdateCommand::ScreenshotSaved(..) => return None,
+    };
+
+    Some(args)
+}
+
 // Custom types to let me define Display trait and custom operations
 #[derive(Debug, Clone, PartialEq, Steel)]
 struct Matrix {
@@ -347,6 +2022,17 @@ impl From<&Matrix> for Matrix4<f32> {
     }
 }
 
+impl From<&Matrix4<f32>> for Matrix {
+    fn from(value: &Matrix4<f32>) -> Self {
+        Matrix {
+            elements: value
+                .row_iter()
+                .map(|row| row.iter().copied().collect())
+                .collect(),
+        }
+    }
+}
+
 impl Display for Matrix {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let space_separate =
@@ -383,19 +2069,28 @@ impl Texture {
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::BTreeMap,
+        path::PathBuf,
         sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel},
         thread,
-        time::Duration,
+        time::{Duration, Instant},
     };
 
     use nalgebra::Matrix4;
 
     use crate::{
-        command::{RenderCommand, StateUpdateCommand, UniformValue},
-        scheme::Matrix,
+        command::{
+            RenderCommand, RenderSnapshot, StateUpdateCommand, TextElement, TimeControl,
+            UniformValue,
+        },
+        diagnostics::parse_compile_log,
+        export::ExportSettings,
+        protocol::EvalResult,
+        scheme::{MAX_STRIKES, Matrix, UpdateRate},
+        stats::FrameStatsSummary,
     };
 
-    use super::NetworkScheme;
+    use super::{NetworkScheme, RenderState};
 
     struct TestHarness {
         state: NetworkScheme,
@@ -423,6 +2118,26 @@ mod tests {
             self.render_receiver
                 .recv_timeout(Duration::from_micros(500))
         }
+
+        fn get_all_events(&mut self) -> Vec<RenderCommand> {
+            let mut events = Vec::new();
+            while let Ok(event) = self.get_last_event() {
+                events.push(event);
+            }
+            events
+        }
+
+        /// Waits for the background thread to bring the render state to where `done` holds.
+        fn wait_for_render_state(&self, done: impl Fn(&RenderState) -> bool) {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !done(&self.state.render_state.lock().unwrap()) {
+                assert!(
+                    Instant::now() < deadline,
+                    "Timed out waiting for the render state"
+                );
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
     }
 
     #[test]
@@ -523,6 +2238,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn draw_text_test() {
+        let mut testharness = TestHarness::new();
+        testharness
+            .state
+            .eval("(draw-text! \"speed\" 42 10 20 24.0 '(1.0 0.5 0))".to_string());
+        assert!(!testharness.state.prev_was_error);
+        assert_eq!(
+            Ok(RenderCommand::DrawText(
+                "speed".to_string(),
+                TextElement {
+                    text: "42".to_string(),
+                    x: 10.0,
+                    y: 20.0,
+                    size: 24.0,
+                    color: (255, 127, 0),
+                }
+            )),
+            testharness.get_last_event()
+        );
+
+        testharness
+            .state
+            .eval("(draw-text! \"caption\" \"Hi\" 10 20 24 '(1.0 0.5))".to_string());
+        assert!(testharness.state.prev_was_error);
+        testharness
+            .state
+            .eval("(draw-text! \"caption\" \"Hi\" 10 20 0 '(1.0 0.5 0.0))".to_string());
+        assert!(testharness.state.prev_was_error);
+        testharness
+            .state
+            .eval("(draw-text! \"caption\" \"Hi\" 10 20 1e9 '(1.0 0.5 0.0))".to_string());
+        assert!(testharness.state.prev_was_error);
+
+        testharness
+            .state
+            .eval("(remove-text! \"speed\") (clear-text!)".to_string());
+        assert_eq!(
+            vec![
+                RenderCommand::RemoveText("speed".to_string()),
+                RenderCommand::ClearText
+            ],
+            testharness.get_all_events()
+        );
+    }
+
     #[test]
     fn screen_size_state_update_test() {
         let mut testharness = TestHarness::new();
@@ -543,35 +2304,941 @@ mod tests {
     }
 
     #[test]
-    fn dynamic_uniform_float_test() {
+    fn shader_diagnostics_test() {
         let mut testharness = TestHarness::new();
 
-        testharness.state.eval("(define global-val 1.0)
-                                (set-dynamic-uniform! \"my-uniform\" (lambda () (set! global-val (+ global-val 2.0)) global-val))".to_string());
+        let log = "0:12(3): error: syntax error\n";
+        testharness
+            .state_sender
+            .send(StateUpdateCommand::ShaderCompileFailed(
+                log.to_string(),
+                parse_compile_log(log, "plasma.frag"),
+            ))
+            .unwrap();
+        testharness.wait_for_render_state(|render_state| !render_state.diagnostics.is_empty());
 
+        let result = testharness.state.eval("(shader-diagnostics)".to_string());
         assert!(!testharness.state.prev_was_error);
-
-        // run a single iteration of dynamic updates
-        testharness.state.run_dynamic_updates().unwrap();
-
-        let event = testharness.get_last_event();
         assert_eq!(
-            Ok(RenderCommand::SetUniform(
-                "my-uniform".to_string(),
-                UniformValue::Float(3.0)
-            )),
-            event
-        )
+            "((\"plasma.frag\" 12 3 \"error\" \"syntax error\"))\n".to_string(),
+            result
+        );
     }
 
-    // TODO: test for stopping/deleting a dynamic uniform?
-    //       what should the test be? receiving no event?
-
-    // TODO: ints! There is some handling of plain integers. Useful in some situations in glsl
+    #[test]
+    fn shader_uniforms_test() {
+        let mut testharness = TestHarness::new();
 
-    // TODO: maybe make some rules for dynamic uniforms to avoid too many pitfalls...
-    //       maybe time limit or detection for if stuck?
-    //    - arguments == 0, only that allowed
+        testharness
+            .state_sender
+            .send(StateUpdateCommand::ShaderUniforms(
+                [("time", "float"), ("color", "vec3")]
+                    .iter()
+                    .map(|(name, ty)| (name.to_string(), ty.to_string()))
+                    .collect(),
+            ))
+            .unwrap();
+        testharness.wait_for_render_state(|render_state| render_state.shader_uniforms.is_some());
 
-    // TODO: input events like keyboard key down and mouse clicks
+        // the wrong type never reaches the renderer
+        let result = testharness
+            .state
+            .eval("(set-uniform! \"color\" 1.0)".to_string());
+        assert!(testharness.state.prev_was_error);
+        assert!(result.contains("Uniform color is a vec3 in the shader, but was given a float"));
+        assert_eq!(Vec::<RenderCommand>::new(), testharness.get_all_events());
+
+        // an unknown name may be about to be added to the shader
+        let result = testharness
+            .state
+            .eval("(set-uniform! \"tiem\" 1.0)".to_string());
+        assert!(!testharness.state.prev_was_error);
+        assert!(
+            result.starts_with(
+                "[WARNING] Uniform tiem is not used by the shader. Did you mean time?\n"
+            )
+        );
+        assert_eq!(
+            vec![RenderCommand::SetUniform(
+                "tiem".to_string(),
+                UniformValue::Float(1.0)
+            )],
+            testharness.get_all_events()
+        );
+
+        // dynamic uniforms fail like any other error
+        testharness.state.eval(
+            "(set-dynamic-uniform! \"color\" (lambda () 1.0))
+             (set-dynamic-uniform-rate! \"color\" \"frame\")"
+                .to_string(),
+        );
+        testharness.state.run_frame_updates();
+        assert_eq!(
+            Some("Uniform color is a vec3 in the shader, but was given a float".to_string()),
+            testharness.state.uniform_status.lock().unwrap()["color"]
+                .last_error
+                .clone()
+        );
+    }
+
+    #[test]
+    fn render_snapshot_test() {
+        let TestHarness {
+            mut state,
+            render_receiver,
+            ..
+        } = TestHarness::new();
+
+        // stands in for the renderer
+        thread::spawn(move || {
+            while let Ok(command) = render_receiver.recv() {
+                if let RenderCommand::GetSnapshot(reply) = command {
+                    reply.send(RenderSnapshot {
+                        uniforms: [
+                            ("time".to_string(), UniformValue::Float(1.5)),
+                            ("color".to_string(), UniformValue::Vector3(0.25, 0.5, 0.75)),
+                        ]
+                        .into_iter()
+                        .collect(),
+                        textures: [("noise".to_string(), (256, 128))].into_iter().collect(),
+                        shader_uniforms: [("noise", "sampler2D"), ("time", "float")]
+                            .iter()
+                            .map(|(name, ty)| (name.to_string(), ty.to_string()))
+                            .collect(),
+                    });
+                }
+            }
+        });
+
+        let result = state.eval(
+            "(list (get-uniform \"time\") (get-uniform \"color\") (get-uniform \"missing\"))"
+                .to_string(),
+        );
+        assert!(!state.prev_was_error);
+        assert_eq!("(1.5 (0.25 0.5 0.75) #false)\n".to_string(), result);
+
+        let result =
+            state.eval("(list (list-uniforms) (shader-uniforms) (list-textures))".to_string());
+        assert!(!state.prev_was_error);
+        assert_eq!(
+            "((\"color\" \"time\") ((\"noise\" \"sampler2D\") (\"time\" \"float\")) ((\"noise\" 256 128)))\n"
+                .to_string(),
+            result
+        );
+    }
+
+    #[test]
+    fn query_after_many_commands_test() {
+        let TestHarness {
+            mut state,
+            render_receiver,
+            ..
+        } = TestHarness::new();
+
+        // stands in for the renderer, handling what is queued once per frame
+        thread::spawn(move || {
+            let mut uniforms = BTreeMap::new();
+            loop {
+                for command in render_receiver.try_iter() {
+                    match command {
+                        RenderCommand::SetUniform(name, value) => {
+                            uniforms.insert(name, value);
+                        }
+                        RenderCommand::GetSnapshot(reply) => reply.send(RenderSnapshot {
+                            uniforms: uniforms.clone(),
+                            textures: BTreeMap::new(),
+                            shader_uniforms: Default::default(),
+                        }),
+                        _ => {}
+                    }
+                }
+                thread::sleep(Duration::from_millis(16));
+            }
+        });
+
+        // far more updates than frames before the query times out
+        let result = state.eval(
+            "(define (set-speeds speed)
+               (when (< speed 500.0)
+                 (set-uniform! \"speed\" speed)
+                 (set-speeds (+ speed 1.0))))
+             (set-speeds 0.0)
+             (= (get-uniform \"speed\") 499.0)"
+                .to_string(),
+        );
+        assert!(!state.prev_was_error);
+        assert_eq!("#true\n".to_string(), result);
+    }
+
+    #[test]
+    fn save_screenshot_test() {
+        let TestHarness {
+            mut state,
+            render_receiver,
+            ..
+        } = TestHarness::new();
+
+        // stands in for the renderer, failing for files it can't save
+        let (requests_sender, requests) = channel();
+        thread::spawn(move || {
+            while let Ok(command) = render_receiver.recv() {
+                if let RenderCommand::SaveScreenshot(path, size, reply) = command {
+                    let result = if path.extension().is_some_and(|ext| ext == "png") {
+                        Ok(())
+                    } else {
+                        Err("Unsupported format".to_string())
+                    };
+                    requests_sender.send((path, size)).unwrap();
+                    reply.send(result);
+                }
+            }
+        });
+
+        state.eval("(save-screenshot \"plasma.png\")".to_string());
+        assert!(!state.prev_was_error);
+        state.eval("(save-screenshot \"poster.png\" 3840 2160)".to_string());
+        assert!(!state.prev_was_error);
+        assert_eq!(
+            vec![
+                (PathBuf::from("plasma.png"), None),
+                (PathBuf::from("poster.png"), Some((3840, 2160)))
+            ],
+            requests.try_iter().collect::<Vec<_>>()
+        );
+
+        let result = state.eval("(save-screenshot \"plasma.xyz\")".to_string());
+        assert!(state.prev_was_error);
+        assert!(result.contains("Unsupported format"));
+        requests.try_iter().for_each(drop);
+
+        // bad sizes never reach the renderer
+        for size in ["100", "0 100", "100.5 100", "100 100 100"] {
+            state.eval(format!("(save-screenshot \"plasma.png\" {})", size));
+            assert!(state.prev_was_error, "{}", size);
+        }
+        assert_eq!(None, requests.try_iter().next());
+    }
+
+    #[test]
+    fn export_frames_test() {
+        let TestHarness {
+            mut state,
+            render_receiver,
+            ..
+        } = TestHarness::new();
+
+        // stands in for the renderer
+        let (requests_sender, requests) = channel();
+        thread::spawn(move || {
+            while let Ok(command) = render_receiver.recv() {
+                if let RenderCommand::StartExport(settings, reply) = command {
+                    requests_sender.send(settings).unwrap();
+                    reply.send(Ok(()));
+                }
+            }
+        });
+
+        state.eval("(export-frames! \"out\" 60 30)".to_string());
+        assert!(!state.prev_was_error);
+        state.eval("(export-frames! \"demo.gif\" 90 24 640 360)".to_string());
+        assert!(!state.prev_was_error);
+        assert_eq!(
+            vec![
+                ExportSettings {
+                    path: PathBuf::from("out"),
+                    frames: 60,
+                    fps: 30.0,
+                    size: None,
+                },
+                ExportSettings {
+                    path: PathBuf::from("demo.gif"),
+                    frames: 90,
+                    fps: 24.0,
+                    size: Some((640, 360)),
+                }
+            ],
+            requests.try_iter().collect::<Vec<_>>()
+        );
+
+        for args in [
+            "0 30",
+            "1.5 30",
+            "60 0",
+            "60 1e-30",
+            "60 1e-300",
+            "60 5000",
+            "60 30 640",
+        ] {
+            state.eval(format!("(export-frames! \"out\" {})", args));
+            assert!(state.prev_was_error, "{}", args);
+        }
+        assert_eq!(None, requests.try_iter().next());
+    }
+
+    #[test]
+    fn export_frame_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(set-time! 100)
+             (set-dynamic-uniform! \"t\" (lambda () (get-elapsed-time)))
+             (set-dynamic-uniform-rate! \"t\" 1000)"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+        testharness.get_all_events();
+
+        // the clock follows the frames, and every dynamic uniform is evaluated whatever its rate
+        testharness.state.run_export_frame(7, 0, 2.5);
+        testharness.state.run_export_frame(8, 1, 2.75);
+        assert_eq!(
+            vec![
+                RenderCommand::UniformBatch(7, vec![("t".to_string(), UniformValue::Float(2.5))]),
+                RenderCommand::UniformBatch(8, vec![("t".to_string(), UniformValue::Float(2.75))])
+            ],
+            testharness.get_all_events()
+        );
+        assert!(testharness.state.is_exporting());
+
+        testharness
+            .state
+            .finish_export(&Ok("Exported 2 frames to out".to_string()));
+        assert!(!testharness.state.is_exporting());
+
+        // e.g, rendered before the renderer found out that writing failed
+        testharness.state.run_export_frame(9, 2, 3.0);
+        assert!(!testharness.state.is_exporting());
+        assert_eq!(
+            vec![RenderCommand::UniformBatch(9, Vec::new())],
+            testharness.get_all_events()
+        );
+        let result = testharness
+            .state
+            .eval("(list (time-paused?) (>= (get-elapsed-time) 100))".to_string());
+        assert_eq!("(#false #true)\n".to_string(), result);
+    }
+
+    #[test]
+    fn export_timeline_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(add-keyframe! \"x\" 0 0.0 \"linear\")
+             (add-keyframe! \"x\" 10 10.0 \"linear\")
+             (timeline-play!)"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+        testharness.state.run_timeline(100.0);
+        testharness.state.run_timeline(101.0);
+        testharness.get_all_events();
+
+        // a playing timeline moves with the exported frames, not by the time the export jumped back
+        testharness.state.run_export_frame(7, 0, 0.0);
+        testharness.state.run_export_frame(8, 1, 0.5);
+        assert_eq!(
+            vec![
+                RenderCommand::UniformBatch(7, vec![("x".to_string(), UniformValue::Float(1.0))]),
+                RenderCommand::UniformBatch(8, vec![("x".to_string(), UniformValue::Float(1.5))])
+            ],
+            testharness.get_all_events()
+        );
+
+        // nor by the time the clock jumps forward again after the export
+        testharness
+            .state
+            .finish_export(&Ok("Exported 2 frames to out".to_string()));
+        testharness.state.run_timeline(101.0);
+        assert_eq!(1.5, testharness.state.timeline.lock().unwrap().position());
+    }
+
+    #[test]
+    #[cfg(not(target_vendor = "apple"))]
+    fn run_single_frame_test() {
+        let mut testharness = TestHarness::new();
+        let shader_uniforms = [("t".to_string(), "float".to_string())].into();
+
+        let script = "(set-time! 100)
+                      (set-dynamic-uniform! \"t\" (lambda () (get-elapsed-time)))
+                      (set-uniform! \"width\" (car (screen-size)))";
+        let result = testharness.state.run_single_frame(
+            (640, 360),
+            shader_uniforms,
+            Some(script.to_string()),
+            1.5,
+        );
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            vec![
+                RenderCommand::SetUniform("width".to_string(), UniformValue::Float(640.0)),
+                RenderCommand::UniformBatch(0, vec![("t".to_string(), UniformValue::Float(1.5))])
+            ],
+            testharness.get_all_events()
+        );
+
+        let result = testharness.state.run_single_frame(
+            (640, 360),
+            Default::default(),
+            Some("(car '())".to_string()),
+            0.0,
+        );
+        assert!(result.unwrap_err().starts_with("Setup script failed"));
+
+        // nothing answers, so waiting for it would only hold the script up
+        let start = Instant::now();
+        for request in [
+            "(get-uniform \"t\")",
+            "(save-screenshot \"plasma.png\")",
+            "(export-frames! \"out\" 60 30)",
+        ] {
+            let result = testharness.state.run_single_frame(
+                (640, 360),
+                Default::default(),
+                Some(request.to_string()),
+                0.0,
+            );
+            assert!(result.is_err(), "{}", request);
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn frame_stats_test() {
+        let mut testharness = TestHarness::new();
+
+        let result = testharness
+            .state
+            .eval("(list (= (list-ref (frame-stats) 0) 0) (list-ref (frame-stats) 2))".to_string());
+        assert_eq!("(#true #false)\n".to_string(), result);
+
+        let stats = FrameStatsSummary {
+            fps: 60.0,
+            cpu_time: Duration::from_micros(500),
+            gpu_time: Some(Duration::from_millis(4)),
+            passes: vec![("shader".to_string(), Duration::from_millis(4))],
+        };
+        testharness
+            .state_sender
+            .send(StateUpdateCommand::FrameStats(stats.clone()))
+            .unwrap();
+        testharness.wait_for_render_state(|render_state| render_state.frame_stats == stats);
+
+        let result = testharness.state.eval(
+            "(define stats (frame-stats))
+             (list (= (list-ref stats 0) 60) (= (list-ref stats 1) 0.5) (= (list-ref stats 2) 4) (car (car (list-ref stats 3))))"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+        assert_eq!("(#true #true #true \"shader\")\n".to_string(), result);
+
+        testharness
+            .state
+            .eval("(show-frame-stats! #true) (show-uniform-inspector! #false)".to_string());
+        assert_eq!(
+            vec![
+                RenderCommand::ShowFrameStats(true),
+                RenderCommand::ShowUniformInspector(false)
+            ],
+            testharness.get_all_events()
+        );
+    }
+
+    #[test]
+    fn dynamic_uniform_float_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval("(define global-val 1.0)
+                                (set-dynamic-uniform! \"my-uniform\" (lambda () (set! global-val (+ global-val 2.0)) global-val))".to_string());
+
+        assert!(!testharness.state.prev_was_error);
+
+        // run a single iteration of dynamic updates
+        testharness.state.run_dynamic_updates();
+
+        let event = testharness.get_last_event();
+        assert_eq!(
+            Ok(RenderCommand::SetUniform(
+                "my-uniform".to_string(),
+                UniformValue::Float(3.0)
+            )),
+            event
+        )
+    }
+
+    #[test]
+    fn event_callback_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval("(define last-size '())
+                                (on-event! \"resize\" (lambda (width height) (set! last-size (list width height))))".to_string());
+        assert!(!testharness.state.prev_was_error);
+
+        testharness
+            .state
+            .run_event_callbacks(&StateUpdateCommand::ScreenSizeChanged(640, 480));
+
+        let result = testharness.state.eval("last-size".to_string());
+        assert_eq!("(640 480)\n".to_string(), result);
+
+        // events without callbacks should simply be ignored
+        testharness
+            .state
+            .run_event_callbacks(&StateUpdateCommand::ShaderReloaded);
+
+        testharness
+            .state
+            .eval("(on-event! \"not-an-event\" (lambda () 1))".to_string());
+        assert!(testharness.state.prev_was_error);
+        testharness
+            .state
+            .eval("(on-event! \"resize\" \"not a function\")".to_string());
+        assert!(testharness.state.prev_was_error);
+        testharness
+            .state
+            .eval("(clear-event-handlers! \"not-an-event\")".to_string());
+        assert!(testharness.state.prev_was_error);
+
+        testharness
+            .state
+            .eval("(clear-event-handlers! \"resize\")".to_string());
+        assert!(!testharness.state.prev_was_error);
+        testharness
+            .state
+            .run_event_callbacks(&StateUpdateCommand::ScreenSizeChanged(320, 240));
+        let result = testharness.state.eval("last-size".to_string());
+        assert_eq!("(640 480)\n".to_string(), result);
+    }
+
+    #[test]
+    fn dynamic_uniform_rate_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(set-dynamic-uniform! \"slow\" (lambda () 1.0))
+             (set-dynamic-uniform-rate! \"slow\" 60000)
+             (set-dynamic-uniform! \"per-frame\" (lambda () 2.0))
+             (set-dynamic-uniform-rate! \"per-frame\" \"frame\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        // slow uniform is due the first time, but not again until its interval has passed
+        testharness.state.run_dynamic_updates();
+        assert_eq!(
+            Ok(RenderCommand::SetUniform(
+                "slow".to_string(),
+                UniformValue::Float(1.0)
+            )),
+            testharness.get_last_event()
+        );
+        testharness.state.run_dynamic_updates();
+        assert!(testharness.get_last_event().is_err());
+
+        // per frame uniforms are only updated when a frame is rendered
+        testharness.state.run_frame_updates();
+        assert_eq!(
+            Ok(RenderCommand::SetUniform(
+                "per-frame".to_string(),
+                UniformValue::Float(2.0)
+            )),
+            testharness.get_last_event()
+        );
+
+        testharness
+            .state
+            .eval("(set-dynamic-uniform-rate! \"slow\" -5)".to_string());
+        assert!(testharness.state.prev_was_error);
+        testharness
+            .state
+            .eval("(set-dynamic-uniform-rate! \"not-dynamic\" 100)".to_string());
+        assert!(testharness.state.prev_was_error);
+
+        // a deleted uniform set again starts over with the default rate
+        testharness.state.eval(
+            "(delete-dynamic-uniform! \"per-frame\")
+             (set-dynamic-uniform! \"per-frame\" (lambda () 3.0))"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+        assert_eq!(
+            UpdateRate::Default,
+            testharness.state.update_rate("per-frame")
+        );
+        assert!(
+            !testharness
+                .state
+                .last_updates
+                .lock()
+                .unwrap()
+                .contains_key("per-frame")
+        );
+        testharness
+            .state
+            .eval("(set-update-interval! 0)".to_string());
+        assert!(testharness.state.prev_was_error);
+
+        testharness
+            .state
+            .eval("(set-update-interval! 16)".to_string());
+        assert!(!testharness.state.prev_was_error);
+        assert_eq!(
+            Duration::from_millis(16),
+            *testharness.state.update_interval.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn frame_sync_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(set-frame-sync! #true)
+             (set-dynamic-uniform! \"time\" (lambda () (get-elapsed-time)))"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+        assert_eq!(
+            Ok(RenderCommand::SetFrameSync(true)),
+            testharness.get_last_event()
+        );
+
+        // the whole batch is sent for the given frame, with time following the frame
+        testharness.state.run_frame_sync(7, 0.5);
+        assert_eq!(
+            Ok(RenderCommand::UniformBatch(
+                7,
+                vec![("time".to_string(), UniformValue::Float(0.5))]
+            )),
+            testharness.get_last_event()
+        );
+    }
+
+    #[test]
+    fn dynamic_uniform_disabled_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(set-dynamic-uniform! \"broken\" (lambda () \"not a uniform value\"))
+             (set-dynamic-uniform-rate! \"broken\" \"frame\")
+             (set-dynamic-uniform! \"working\" (lambda () 1.0))
+             (set-dynamic-uniform-rate! \"working\" \"frame\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        // the failing uniform is disabled after a few strikes, while the other keeps updating
+        for _ in 0..MAX_STRIKES {
+            testharness.state.run_frame_updates();
+            assert!(
+                testharness
+                    .get_all_events()
+                    .contains(&RenderCommand::SetUniform(
+                        "working".to_string(),
+                        UniformValue::Float(1.0)
+                    ))
+            );
+        }
+        let dynamic_uniforms = testharness.state.dynamic_uniforms();
+        assert!(!dynamic_uniforms.contains_key("broken"));
+        assert!(dynamic_uniforms.contains_key("working"));
+
+        // nothing of its old rate is left when set again
+        assert_eq!(UpdateRate::Default, testharness.state.update_rate("broken"));
+        assert!(
+            !testharness
+                .state
+                .last_updates
+                .lock()
+                .unwrap()
+                .contains_key("broken")
+        );
+    }
+
+    #[test]
+    fn dynamic_uniform_budget_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(define (count-down n) (if (= n 0) 1.0 (count-down (- n 1))))
+             (set-dynamic-uniform-budget! 0.001)
+             (set-dynamic-uniform! \"slow\" (lambda () (count-down 100000)))
+             (set-dynamic-uniform-rate! \"slow\" \"frame\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        for _ in 0..MAX_STRIKES {
+            testharness.state.run_frame_updates();
+        }
+        assert!(!testharness.state.dynamic_uniforms().contains_key("slow"));
+    }
+
+    #[test]
+    fn dynamic_uniform_endless_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(set-dynamic-uniform-budget! 5)
+             (set-dynamic-uniform! \"endless\" (lambda () (let loop () (loop))))
+             (set-dynamic-uniform-rate! \"endless\" \"frame\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        // each update is interrupted when the budget is up, instead of freezing the thread
+        for _ in 0..MAX_STRIKES {
+            testharness.state.run_frame_updates();
+        }
+        assert!(!testharness.state.dynamic_uniforms().contains_key("endless"));
+
+        // and the VM still works afterwards
+        let result = testharness.state.eval("(+ 1 2)".to_string());
+        assert_eq!("3\n".to_string(), result);
+    }
+
+    #[test]
+    fn dynamic_uniform_errors_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(define broken? #true)
+             (set-dynamic-uniform! \"flaky\" (lambda () (if broken? \"not a uniform value\" 1.0)))
+             (set-dynamic-uniform-rate! \"flaky\" \"frame\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        // errors are kept per uniform, and sent to the renderer for display
+        testharness.state.run_frame_updates();
+        let errors = testharness
+            .state
+            .eval("(length (dynamic-uniform-errors))".to_string());
+        assert_eq!("1\n".to_string(), errors);
+        assert!(
+            testharness
+                .get_all_events()
+                .iter()
+                .any(|event| matches!(event, RenderCommand::SetUniformErrors(errors) if errors.len() == 1 && errors[0].0 == "flaky"))
+        );
+
+        // and cleared when it works again
+        testharness.state.eval("(set! broken? #false)".to_string());
+        testharness.state.run_frame_updates();
+        let errors = testharness
+            .state
+            .eval("(length (dynamic-uniform-errors))".to_string());
+        assert_eq!("0\n".to_string(), errors);
+        assert!(
+            testharness
+                .get_all_events()
+                .contains(&RenderCommand::SetUniformErrors(vec![]))
+        );
+    }
+
+    #[test]
+    fn dynamic_uniform_validation_test() {
+        let mut testharness = TestHarness::new();
+
+        // only functions without arguments are accepted
+        testharness
+            .state
+            .eval("(set-dynamic-uniform! \"not-a-lambda\" 1.0)".to_string());
+        assert!(testharness.state.prev_was_error);
+        testharness
+            .state
+            .eval("(set-dynamic-uniform! \"with-args\" (lambda (x) x))".to_string());
+        assert!(testharness.state.prev_was_error);
+        assert!(testharness.state.dynamic_uniforms().is_empty());
+
+        // built-in functions have no arity to check, but work like lambdas
+        testharness
+            .state
+            .eval("(set-dynamic-uniform! \"t\" get-elapsed-time)".to_string());
+        assert!(!testharness.state.prev_was_error);
+        assert!(testharness.state.dynamic_uniforms().contains_key("t"));
+
+        testharness
+            .state
+            .eval("(delete-dynamic-uniform! \"unknown\")".to_string());
+        assert!(testharness.state.prev_was_error);
+    }
+
+    #[test]
+    fn delete_dynamic_uniform_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(set-dynamic-uniform! \"my-uniform\" (lambda () 1.0))
+             (delete-dynamic-uniform! \"my-uniform\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        // deleted uniforms should not be updated anymore
+        testharness.state.run_dynamic_updates();
+        assert!(testharness.get_last_event().is_err());
+
+        testharness
+            .state
+            .eval("(delete-dynamic-uniform! \"my-uniform\")".to_string());
+        assert!(testharness.state.prev_was_error);
+    }
+
+    #[test]
+    fn delete_disabled_dynamic_uniform_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(set-dynamic-uniform! \"broken\" (lambda () \"not a uniform value\"))
+             (set-dynamic-uniform-rate! \"broken\" \"frame\")"
+                .to_string(),
+        );
+        for _ in 0..MAX_STRIKES {
+            testharness.state.run_frame_updates();
+        }
+        assert!(!testharness.state.dynamic_uniforms().contains_key("broken"));
+        testharness.get_all_events();
+
+        // the reason it was disabled is shown until the uniform is deleted
+        testharness
+            .state
+            .eval("(delete-dynamic-uniform! \"broken\")".to_string());
+        assert!(!testharness.state.prev_was_error);
+        let errors = testharness
+            .state
+            .eval("(length (dynamic-uniform-errors))".to_string());
+        assert_eq!("0\n".to_string(), errors);
+        assert_eq!(
+            vec![RenderCommand::SetUniformErrors(vec![])],
+            testharness.get_all_events()
+        );
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let mut testharness = TestHarness::new();
+
+        assert_eq!(
+            EvalResult {
+                value: Ok("3".to_string()),
+                stdout: String::new(),
+            },
+            testharness.state.evaluate("(+ 1 2)".to_string())
+        );
+        assert!(
+            testharness
+                .state
+                .evaluate("(car '())".to_string())
+                .value
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn output_test() {
+        let mut testharness = TestHarness::new();
+
+        // printed output goes with the result to whoever asked
+        assert_eq!(
+            EvalResult {
+                value: Ok("3".to_string()),
+                stdout: "hi\n(1 2)\n\"quoted\"".to_string(),
+            },
+            testharness.state.evaluate(
+                "(display \"hi\") (newline) (displayln '(1 2)) (write \"quoted\") (+ 1 2)"
+                    .to_string()
+            )
+        );
+
+        // printing to a port of your own still works
+        let result = testharness.state.evaluate(
+            "(define port (open-output-string))
+             (display \"to the port\" port)
+             (newline port)
+             (get-output-string port)"
+                .to_string(),
+        );
+        assert_eq!(Ok("\"to the port\\n\"".to_string()), result.value);
+        assert_eq!("", result.stdout);
+
+        // output from dynamic uniforms is kept for everyone
+        testharness.state.eval(
+            "(set-dynamic-uniform! \"x\" (lambda () (display \"x\") 1.0))
+             (set-dynamic-uniform-rate! \"x\" \"frame\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+        testharness.state.run_frame_updates();
+        assert_eq!("x", testharness.state.take_output());
+    }
+
+    #[test]
+    fn timeline_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(add-keyframe! \"x\" 0 0.0 \"linear\")
+             (add-keyframe! \"x\" 2 10.0 \"ease-in-out-sine\")
+             (add-keyframe! \"x\" 4 20.0 \"linear\")
+             (timeline-seek! 1)"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        // scrubbing a paused timeline sends the uniforms at that position
+        testharness.state.run_timeline(0.0);
+        assert_eq!(
+            Ok(RenderCommand::UniformBatch(
+                0,
+                vec![("x".to_string(), UniformValue::Float(5.0))]
+            )),
+            testharness.get_last_event()
+        );
+
+        // nothing new to send until something changes
+        testharness.state.run_timeline(1.0);
+        assert!(testharness.get_last_event().is_err());
+
+        testharness
+            .state
+            .eval("(add-keyframe! \"x\" 6 1.0 \"wobbly\")".to_string());
+        assert!(testharness.state.prev_was_error);
+        testharness
+            .state
+            .eval("(add-keyframe! \"x\" 6 '(1.0 2.0 3.0) \"linear\")".to_string());
+        assert!(testharness.state.prev_was_error);
+    }
+
+    #[test]
+    fn clock_test() {
+        let mut testharness = TestHarness::new();
+
+        testharness.state.eval(
+            "(pause-time)
+             (set-time! 2.5)
+             (set-dynamic-uniform! \"time\" (lambda () (get-elapsed-time)))
+             (set-dynamic-uniform-rate! \"time\" \"frame\")"
+                .to_string(),
+        );
+        assert!(!testharness.state.prev_was_error);
+
+        // dynamic uniforms follow the paused clock
+        testharness.state.run_frame_updates();
+        assert_eq!(
+            Ok(RenderCommand::SetUniform(
+                "time".to_string(),
+                UniformValue::Float(2.5)
+            )),
+            testharness.get_last_event()
+        );
+
+        // keyboard shortcuts move the clock too
+        testharness.state.control_time(TimeControl::Skip(-1.0));
+        assert_eq!(1.5, testharness.state.time());
+
+        testharness.state.eval("(set-time! \"soon\")".to_string());
+        assert!(testharness.state.prev_was_error);
+    }
+
+    // TODO: ints! There is some handling of plain integers. Useful in some situations in glsl
 }
@@ -0,0 +1,127 @@
+//! The last version of each shader that compiled, kept on disk between sessions. Starting with a broken shader can then show
+//! its last good version instead of a placeholder.
+
+use std::{
+    env,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub struct ShaderCache {
+    dir: PathBuf,
+}
+
+impl Default for ShaderCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShaderCache {
+    /// Cache in the user's cache directory.
+    pub fn new() -> Self {
+        Self::in_dir(default_dir(|name| env::var_os(name)))
+    }
+
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Source of the shader file the last time it compiled, if ever.
+    pub fn load(&self, shader_file: &Path) -> Option<String> {
+        fs::read_to_string(self.path_for(shader_file)).ok()
+    }
+
+    /// Remembers the source of a shader file that compiled.
+    pub fn store(&self, shader_file: &Path, source: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // written next to it first, so a crash never leaves half a shader behind
+        let path = self.path_for(shader_file);
+        let partial_path = path.with_extension("partial");
+        fs::write(&partial_path, source)?;
+        fs::rename(partial_path, path)
+    }
+
+    /// File the source is kept in. Named after the shader file, with a hash of the full path as the same name can be in many directories.
+    fn path_for(&self, shader_file: &Path) -> PathBuf {
+        let full_path = fs::canonicalize(shader_file).unwrap_or_else(|_| shader_file.to_path_buf());
+        let name = shader_file
+            .file_name()
+            .map_or("shader".into(), |name| name.to_string_lossy());
+        self.dir.join(format!(
+            "{:016x}-{}",
+            fnv1a(full_path.to_string_lossy().as_bytes()),
+            name
+        ))
+    }
+}
+
+/// Cache directory of the platform, with environment variables looked up by `env`.
+fn default_dir(env: impl Fn(&str) -> Option<OsString>) -> PathBuf {
+    let cache_dir = if cfg!(windows) {
+        env("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    cache_dir.unwrap_or_else(env::temp_dir).join("sepl")
+}
+
+/// FNV-1a hash. Unlike the hashers in std, it is the same between Rust versions, which matters for file names.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, ffi::OsString, fs, path::Path, process};
+
+    use super::{ShaderCache, default_dir};
+
+    #[test]
+    fn store_test() {
+        let dir = env::temp_dir().join(format!("sepl-cache-test-{}", process::id()));
+        let cache = ShaderCache::in_dir(&dir);
+        let plasma = Path::new("/shaders/plasma.frag");
+        assert_eq!(None, cache.load(plasma));
+
+        cache.store(plasma, "void main() {}").unwrap();
+        assert_eq!(Some("void main() {}".to_string()), cache.load(plasma));
+
+        // same name in another directory is another shader
+        let other_plasma = Path::new("/other/shaders/plasma.frag");
+        assert_eq!(None, cache.load(other_plasma));
+        cache.store(other_plasma, "void main() { }").unwrap();
+        assert_eq!(Some("void main() {}".to_string()), cache.load(plasma));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn default_dir_test() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        assert_eq!(
+            Path::new("/cache/sepl"),
+            default_dir(env(&[
+                ("XDG_CACHE_HOME", "/cache"),
+                ("HOME", "/home/marie")
+            ]))
+        );
+        assert_eq!(
+            Path::new("/home/marie/.cache/sepl"),
+            default_dir(env(&[("HOME", "/home/marie")]))
+        );
+        assert_eq!(env::temp_dir().join("sepl"), default_dir(env(&[])));
+    }
+}
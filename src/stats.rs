@@ -0,0 +1,141 @@
+//! Performance statistics of the renderer, averaged over the most recent frames.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Number of frames the statistics are averaged over.
+const WINDOW: usize = 60;
+
+/// Averages of the recent frames, as shown in the overlay and given to Scheme.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrameStatsSummary {
+    pub fps: f32,
+    /// Time spent on the CPU to draw a frame
+    pub cpu_time: Duration,
+    /// Time the GPU spent on all passes, if timer queries are supported
+    pub gpu_time: Option<Duration>,
+    /// GPU time of each render pass, by name
+    pub passes: Vec<(String, Duration)>,
+}
+
+#[derive(Default)]
+pub struct FrameStats {
+    // start and CPU time of the recent frames
+    frames: VecDeque<(Instant, Duration)>,
+    // GPU times of the recent frames, by pass. Arrives some frames late, when the GPU is done
+    gpu_times: BTreeMap<String, VecDeque<Duration>>,
+}
+
+impl FrameStats {
+    pub fn record_frame(&mut self, start: Instant, cpu_time: Duration) {
+        push_recent(&mut self.frames, (start, cpu_time));
+    }
+
+    pub fn record_gpu_time(&mut self, pass: &str, time: Duration) {
+        push_recent(self.gpu_times.entry(pass.to_string()).or_default(), time);
+    }
+
+    /// Frames started per second. Needs at least two frames.
+    pub fn fps(&self) -> f32 {
+        match (self.frames.front(), self.frames.back()) {
+            (Some((first, _)), Some((last, _))) if last > first => {
+                (self.frames.len() - 1) as f32 / (*last - *first).as_secs_f32()
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub fn summary(&self) -> FrameStatsSummary {
+        let passes: Vec<(String, Duration)> = self
+            .gpu_times
+            .iter()
+            .filter_map(|(pass, times)| Some((pass.clone(), average(times.iter().copied())?)))
+            .collect();
+
+        FrameStatsSummary {
+            fps: self.fps(),
+            cpu_time: average(self.frames.iter().map(|(_, cpu_time)| *cpu_time))
+                .unwrap_or_default(),
+            gpu_time: (!passes.is_empty()).then(|| passes.iter().map(|(_, time)| *time).sum()),
+            passes,
+        }
+    }
+}
+
+impl FrameStatsSummary {
+    /// Lines of text for the overlay, with times in milliseconds.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{:.1} FPS\nCPU {:.2} ms", self.fps, millis(self.cpu_time));
+        match self.gpu_time {
+            Some(gpu_time) => text.push_str(&format!("\nGPU {:.2} ms", millis(gpu_time))),
+            None => text.push_str("\nGPU n/a"),
+        }
+        // no need to repeat the total for a single pass
+        if self.passes.len() > 1 {
+            for (pass, time) in &self.passes {
+                text.push_str(&format!("\n  {} {:.2} ms", pass, millis(*time)));
+            }
+        }
+        text
+    }
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Adds to the back, and drops the oldest values outside of the window.
+fn push_recent<T>(values: &mut VecDeque<T>, value: T) {
+    values.push_back(value);
+    if values.len() > WINDOW {
+        values.pop_front();
+    }
+}
+
+fn average(durations: impl ExactSizeIterator<Item = Duration>) -> Option<Duration> {
+    let count = durations.len() as u32;
+    (count > 0).then(|| durations.sum::<Duration>() / count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FrameStats, FrameStatsSummary, WINDOW};
+
+    #[test]
+    fn summary_test() {
+        let mut stats = FrameStats::default();
+        assert_eq!(FrameStatsSummary::default(), stats.summary());
+
+        // only the most recent frames count
+        let start = Instant::now();
+        stats.record_frame(start, Duration::from_millis(100));
+        for frame in 0..=WINDOW as u64 {
+            stats.record_frame(
+                start + Duration::from_millis(20 * frame),
+                Duration::from_millis(2),
+            );
+        }
+        stats.record_gpu_time("shader", Duration::from_millis(4));
+        stats.record_gpu_time("shader", Duration::from_millis(6));
+
+        let summary = stats.summary();
+        assert!((summary.fps - 50.0).abs() < 0.01);
+        assert_eq!(Duration::from_millis(2), summary.cpu_time);
+        assert_eq!(Some(Duration::from_millis(5)), summary.gpu_time);
+        assert_eq!(
+            vec![("shader".to_string(), Duration::from_millis(5))],
+            summary.passes
+        );
+        assert_eq!("50.0 FPS\nCPU 2.00 ms\nGPU 5.00 ms", summary.to_text());
+
+        stats.record_gpu_time("bloom", Duration::from_millis(1));
+        assert_eq!(
+            "50.0 FPS\nCPU 2.00 ms\nGPU 6.00 ms\n  bloom 1.00 ms\n  shader 5.00 ms",
+            stats.summary().to_text()
+        );
+    }
+}
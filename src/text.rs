@@ -1,29 +1,77 @@
+use std::collections::BTreeMap;
+
 use glium::{
-    Blend, DrawParameters, Program, Surface, Texture2d, VertexBuffer, backend::Facade,
+    Blend, DrawParameters, Program, Rect, Surface, Texture2d, VertexBuffer, backend::Facade,
     index::NoIndices, uniform, uniforms::EmptyUniforms,
 };
 use rusttype::{Font, Scale, point};
 
-use crate::geometry::{SQUARE, Vertex};
+use crate::{
+    command::TextElement,
+    geometry::{SQUARE, Vertex},
+};
 
 const TEXT_RENDER_VERTEX_SHADER: &str = include_str!("../shaders/pass_text.vert");
 
 const TEXT_RENDER_FRAGMENT_SHADER: &str = include_str!("../shaders/text.frag");
 
+const HUD_TEXT_VERTEX_SHADER: &str = include_str!("../shaders/hud_text.vert");
+
 const SOLID_PLANE_VERTEX_SHADER: &str = include_str!("../shaders/pass.vert");
 
 const SOLID_PLANE_FRAGMENT_SHADER: &str = include_str!("../shaders/solid.frag");
 
+/// A line of overlay text, and its color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLine {
+    pub text: String,
+    pub color: (u8, u8, u8),
+}
+
+impl TextLine {
+    pub fn new(text: impl Into<String>, color: (u8, u8, u8)) -> Self {
+        Self {
+            text: text.into(),
+            color,
+        }
+    }
+}
+
+const FOOTER_COLOR: (u8, u8, u8) = (170, 170, 170);
+
+// space around text elements, so glyphs reaching outside their box aren't cut off
+const HUD_MARGIN: f32 = 2.0;
+
+/// A text element, and its texture once rendered.
+struct HudText {
+    element: TextElement,
+    texture: Option<Texture2d>,
+}
+
 pub struct TextRenderer {
     font: Font<'static>,
     program: Program,
+    hud_program: Program,
     solid_background_program: Program,
     vertex_buffer: VertexBuffer<Vertex>,
     index_buffer: NoIndices,
 
+    // first row shown, when the text doesn't fit on screen
+    scroll: usize,
+
     // some utility caching to avoid recreating same text twice
-    prev_text: Option<String>,
+    prev_text: Option<Vec<TextLine>>,
+    prev_size: (u32, u32),
+    prev_font_size: f32,
+    // the text wrapped to the area it was last drawn in
+    prev_rows: Vec<TextLine>,
+    prev_scroll: usize,
     prev_texture: Option<Texture2d>,
+
+    // text elements drawn on top of the shader, by id. Sorted to always draw them in the same order
+    hud: BTreeMap<String, HudText>,
+    // frame statistics and such, drawn apart from the text elements so Scheme can't remove them
+    info: Option<HudText>,
 }
 
 impl TextRenderer {
@@ -35,6 +83,13 @@ impl TextRenderer {
             None,
         )
         .unwrap();
+        let hud_program = Program::from_source(
+            display,
+            HUD_TEXT_VERTEX_SHADER,
+            TEXT_RENDER_FRAGMENT_SHADER,
+            None,
+        )
+        .unwrap();
         let solid_background_program = Program::from_source(
             display,
             SOLID_PLANE_VERTEX_SHADER,
@@ -49,50 +104,186 @@ impl TextRenderer {
         Self {
             font: Font::try_from_bytes(include_bytes!("../fonts/OpenSans-Bold.ttf")).unwrap(),
             program,
+            hud_program,
             solid_background_program,
             vertex_buffer,
             index_buffer,
+            scroll: 0,
             prev_text: None,
+            prev_size: (0, 0),
+            prev_font_size: 0.0,
+            prev_rows: Vec::new(),
+            prev_scroll: 0,
             prev_texture: None,
+            hud: BTreeMap::new(),
+            info: None,
         }
     }
 
-    fn render_text_to_texture<T: Facade>(&self, display: &T, text: &str) -> Texture2d {
-        let mut result = vec![vec![(0, 0, 0, 0); 800]; 500];
+    /// Adds or replaces a text element. It is only rendered again if it changed.
+    pub fn set_hud_text(&mut self, id: String, element: TextElement) {
+        if self.hud.get(&id).is_none_or(|text| text.element != element) {
+            self.hud.insert(
+                id,
+                HudText {
+                    element,
+                    texture: None,
+                },
+            );
+        }
+    }
+
+    pub fn remove_hud_text(&mut self, id: &str) {
+        self.hud.remove(id);
+    }
+
+    pub fn clear_hud(&mut self) {
+        self.hud.clear();
+    }
+
+    /// Scrolls the text by a number of rows, negative to scroll up. Stops at the top and bottom of the text.
+    pub fn scroll(&mut self, rows: isize) {
+        self.scroll = self.scroll.saturating_add_signed(rows);
+    }
+
+    /// Width of the text in pixels when drawn at the given scale.
+    fn text_width(&self, text: &str, scale: Scale) -> f32 {
+        self.font
+            .layout(text, scale, point(0.0, 0.0))
+            .last()
+            .map_or(0.0, |glyph| {
+                glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+            })
+    }
+
+    /// Where each character of the text starts when drawn at the given scale, followed by where the text ends.
+    fn char_positions(&self, text: &str, scale: Scale) -> Vec<f32> {
+        let mut positions = Vec::new();
+        let mut end = 0.0;
+        for glyph in self.font.layout(text, scale, point(0.0, 0.0)) {
+            let x = glyph.position().x;
+            positions.push(x);
+            end = x + glyph.unpositioned().h_metrics().advance_width;
+        }
+        positions.push(end);
+        positions
+    }
 
-        // TODO: maybe prettier formatting?
-        // only take the last first error line. The rest are usually caused by this first one
-        let text = wrap_words(text.lines().nth(0).unwrap_or("").to_string(), 40);
-        let mut y_pos = 48.0;
-        for line in text.lines() {
-            let scale = Scale::uniform(48.0);
-            let glyphs = self.font.layout(line, scale, point(20.0, y_pos));
+    /// Draws the rows into a texture of the given size, with a margin around them. Anything outside of it is cut off. The
+    /// size is capped at the largest viewport of the context, as text beyond it can't be shown anyway.
+    fn render_text_to_texture<T: Facade>(
+        &self,
+        display: &T,
+        rows: &[TextLine],
+        size: (u32, u32),
+        scale: Scale,
+        margin: f32,
+    ) -> Result<Texture2d, String> {
+        // a huge font size or very long lines would take all memory otherwise
+        let (max_width, max_height) = display.get_context().get_capabilities().max_viewport_dims;
+        let width = size.0.min(max_width.max(1) as u32);
+        let height = size.1.min(max_height.max(1) as u32);
+        let mut result = vec![vec![(0, 0, 0, 0); width as usize]; height as usize];
 
-            // TODO: use glyphs to decide on a suitable size
-            for glyph in glyphs {
+        let mut y_pos = margin + self.font.v_metrics(scale).ascent;
+        for row in rows {
+            let (r, g, b) = row.color;
+            for glyph in self.font.layout(&row.text, scale, point(margin, y_pos)) {
                 // only rasterize text that have data
                 if let Some(bounding_box) = glyph.pixel_bounding_box() {
                     glyph.draw(|x, y, v| {
-                        result[(bounding_box.min.y as u32 + y) as usize]
-                            [(bounding_box.min.x as u32 + x) as usize] =
-                            (255, 0, 0, (v * 255.0) as u8);
+                        let x = bounding_box.min.x + x as i32;
+                        let y = bounding_box.min.y + y as i32;
+                        if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+                            return;
+                        }
+                        // glyphs may overlap a bit, keep the strongest
+                        let pixel = &mut result[y as usize][x as usize];
+                        let alpha = (v * 255.0) as u8;
+                        if alpha >= pixel.3 {
+                            *pixel = (r, g, b, alpha);
+                        }
                     });
                 }
             }
 
-            // tweaked number for spacing between lines
-            y_pos += 32.0;
+            y_pos += scale.y * LINE_SPACING;
         }
 
-        Texture2d::new(display, result).unwrap()
+        Texture2d::new(display, result).map_err(|err| err.to_string())
     }
 
-    /// Render text to the specified surface.
-    pub fn render_text<T: Facade, S: Surface>(&mut self, display: &T, surface: &mut S, text: &str) {
-        if Some(text.to_string()) != self.prev_text {
-            let texture = self.render_text_to_texture(display, text);
-            self.prev_texture = Some(texture);
-            self.prev_text = Some(text.to_string());
+    /// Render lines of text to an area of the specified surface, on a dark background. Text is sized after the surface,
+    /// wrapped to the width of the area, and scrolled if there are more lines than fit.
+    pub fn render_text<T: Facade, S: Surface>(
+        &mut self,
+        display: &T,
+        surface: &mut S,
+        lines: &[TextLine],
+        area: Rect,
+    ) {
+        let size = (area.width, area.height);
+        // nothing to draw on when minimized
+        if size.0 == 0 || size.1 == 0 {
+            return;
+        }
+
+        let text_changed = self.prev_text.as_deref() != Some(lines);
+        // new text starts from the top
+        if text_changed {
+            self.scroll = 0;
+        }
+
+        let scale = Scale::uniform(font_size(surface.get_dimensions().1));
+        let margin = scale.y;
+        // wrapping lays out all of the text, so it is only done again when the text or its size changes
+        let rewrapped = text_changed || self.prev_size != size || self.prev_font_size != scale.y;
+        if rewrapped {
+            let max_width = size.0 as f32 - 2.0 * margin;
+            self.prev_rows = lines
+                .iter()
+                .flat_map(|line| {
+                    let positions = self.char_positions(&line.text, scale);
+                    wrap_line(&line.text, &positions, max_width)
+                        .into_iter()
+                        .map(|text| TextLine::new(text, line.color))
+                })
+                .collect();
+            self.prev_text = Some(lines.to_vec());
+            self.prev_size = size;
+            self.prev_font_size = scale.y;
+        }
+
+        let rows = &self.prev_rows;
+        let visible_rows =
+            (((size.1 as f32 - 2.0 * margin) / (scale.y * LINE_SPACING)) as usize).max(1);
+        // last row tells where we are, when not all rows fit
+        let shown_rows = if rows.len() > visible_rows {
+            (visible_rows - 1).max(1)
+        } else {
+            rows.len()
+        };
+        self.scroll = self.scroll.min(rows.len() - shown_rows);
+
+        if rewrapped || self.prev_scroll != self.scroll {
+            let mut shown: Vec<TextLine> = rows[self.scroll..self.scroll + shown_rows].to_vec();
+            if shown_rows < rows.len() {
+                shown.push(TextLine::new(
+                    format!(
+                        "Lines {}-{} of {}. Scroll with the mouse wheel or Page Up/Down",
+                        self.scroll + 1,
+                        self.scroll + shown_rows,
+                        rows.len()
+                    ),
+                    FOOTER_COLOR,
+                ));
+            }
+            let texture = self.render_text_to_texture(display, &shown, size, scale, margin);
+            if let Err(err) = &texture {
+                eprintln!("[ERROR] Could not draw the text: {}", err);
+            }
+            self.prev_texture = texture.ok();
+            self.prev_scroll = self.scroll;
         }
 
         // solid plane to make text pop out more if the user have an active fragment shader
@@ -104,51 +295,231 @@ impl TextRenderer {
                 &EmptyUniforms,
                 &DrawParameters {
                     blend: Blend::alpha_blending(),
+                    viewport: Some(area),
                     ..Default::default()
                 },
             )
             .expect("Could not draw solid plane");
 
-        // we know at this point that there should always be a texture present
-        let texture = self.prev_texture.as_ref().unwrap();
+        let Some(texture) = &self.prev_texture else {
+            return;
+        };
 
         surface.draw(&self.vertex_buffer, self.index_buffer, &self.program, &uniform! {
             font_texture: texture.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear).minify_filter(glium::uniforms::MinifySamplerFilter::Linear)
         }, &DrawParameters {
             blend: Blend::alpha_blending(),
+            viewport: Some(area),
             ..Default::default()
         }).expect("Could not draw text to screen");
     }
-}
 
-/// Takes a maximum length as an input and wraps the words by putting as many words on a line as possible.
-fn wrap_words(text: String, line_length: usize) -> String {
-    let mut leftover = line_length;
-    let mut result = String::with_capacity(text.len());
+    /// Texture for a text element, just big enough for its lines. None for empty text.
+    fn render_hud_texture<T: Facade>(
+        &self,
+        display: &T,
+        element: &TextElement,
+    ) -> Result<Option<Texture2d>, String> {
+        let scale = Scale::uniform(element.size);
+        let rows: Vec<TextLine> = element
+            .text
+            .lines()
+            .map(|line| TextLine::new(line, element.color))
+            .collect();
+        let width = rows
+            .iter()
+            .map(|row| self.text_width(&row.text, scale))
+            .fold(0.0, f32::max);
+        if width <= 0.0 {
+            return Ok(None);
+        }
+
+        let height = rows.len() as f32 * scale.y * LINE_SPACING;
+        let size = (
+            (width + 2.0 * HUD_MARGIN).ceil() as u32,
+            (height + 2.0 * HUD_MARGIN).ceil() as u32,
+        );
+        self.render_text_to_texture(display, &rows, size, scale, HUD_MARGIN)
+            .map(Some)
+    }
+
+    /// Draws all text elements on the surface. Parts outside of the surface are cut off.
+    pub fn render_hud<T: Facade, S: Surface>(&mut self, display: &T, surface: &mut S) {
+        let (width, height) = surface.get_dimensions();
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        for id in self.hud.keys().cloned().collect::<Vec<_>>() {
+            if self.hud[&id].texture.is_none() {
+                match self.render_hud_texture(display, &self.hud[&id].element) {
+                    Ok(texture) => self.hud.get_mut(&id).unwrap().texture = texture,
+                    // removed, so it isn't tried again every frame
+                    Err(err) => {
+                        eprintln!("[ERROR] Could not draw text {}: {}. Removed", id, err);
+                        self.hud.remove(&id);
+                    }
+                }
+            }
+        }
+
+        for text in self.hud.values() {
+            self.draw_hud_text(surface, text);
+        }
+    }
+
+    /// Draws information about the renderer, like frame statistics, in the top left corner. Sized after the surface.
+    pub fn render_info<T: Facade, S: Surface>(&mut self, display: &T, surface: &mut S, text: &str) {
+        let size = font_size(surface.get_dimensions().1);
+        let element = TextElement {
+            text: text.to_string(),
+            x: size,
+            y: size,
+            size,
+            color: (255, 255, 255),
+        };
+        if self
+            .info
+            .as_ref()
+            .is_none_or(|info| info.element != element)
+        {
+            let texture = self
+                .render_hud_texture(display, &element)
+                .unwrap_or_else(|err| {
+                    eprintln!("[ERROR] Could not draw the frame statistics: {}", err);
+                    None
+                });
+            self.info = Some(HudText { element, texture });
+        }
 
-    for word in text.split_whitespace() {
-        if (word.len() + 1) > leftover {
-            result += "\n";
-            leftover = line_length;
+        if let Some(info) = &self.info {
+            self.draw_hud_text(surface, info);
         }
-        result += word;
-        result += " ";
-        leftover = leftover.saturating_sub(word.len() + 1);
     }
 
-    result
+    fn draw_hud_text<S: Surface>(&self, surface: &mut S, text: &HudText) {
+        let (width, height) = surface.get_dimensions();
+        let Some(texture) = &text.texture else {
+            return;
+        };
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        // the text box in pixels, with the margin outside of the given position
+        let left = text.element.x - HUD_MARGIN;
+        let top = text.element.y - HUD_MARGIN;
+        let right = left + texture.width() as f32;
+        let bottom = top + texture.height() as f32;
+        let to_x = |x: f32| 2.0 * x / width as f32 - 1.0;
+        let to_y = |y: f32| 1.0 - 2.0 * y / height as f32;
+
+        surface.draw(&self.vertex_buffer, self.index_buffer, &self.hud_program, &uniform! {
+            bounds: [to_x(left), to_y(bottom), to_x(right), to_y(top)],
+            font_texture: texture.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest).minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
+        }, &DrawParameters {
+            blend: Blend::alpha_blending(),
+            ..Default::default()
+        }).expect("Could not draw text to screen");
+    }
+}
+
+/// Font size in pixels for a screen of the given height. Scales with the screen, but stays readable on small ones.
+fn font_size(height: u32) -> f32 {
+    (height as f32 / 36.0).clamp(14.0, 32.0)
+}
+
+/// Space between rows, relative to the font size.
+const LINE_SPACING: f32 = 1.25;
+
+/// Splits a line into rows no wider than `max_width`, given where each character starts when the line is laid out in
+/// one row, followed by where it ends. Breaks after the last space that fits, or anywhere in words too long for a row.
+/// Leading whitespace is kept, so indented source code stays indented.
+fn wrap_line(line: &str, positions: &[f32], max_width: f32) -> Vec<String> {
+    // byte index of each character and of the end of the line, to go with the positions
+    let indices: Vec<usize> = line
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([line.len()])
+        .collect();
+    let last = indices.len() - 1;
+    let fits = |start: usize, end: usize| positions[end] - positions[start] <= max_width;
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    while start < last && !fits(start, last) {
+        // at least one character per row, to always make progress
+        let mut end = (start + 1..last)
+            .take_while(|&end| fits(start, end))
+            .last()
+            .unwrap_or(start + 1);
+        // break before a space if the row ends in the middle of a word
+        let row = &line[indices[start]..indices[end]];
+        if !line[indices[end]..].starts_with(' ')
+            && let Some(space) = row.rfind(' ').filter(|&i| !row[..i].trim().is_empty())
+        {
+            end = start + row[..space].chars().count();
+        }
+        rows.push(line[indices[start]..indices[end]].trim_end().to_string());
+        start = end;
+        while start < last && line[indices[start]..].starts_with(' ') {
+            start += 1;
+        }
+    }
+    let rest = &line[indices[start]..];
+    if !rest.is_empty() || rows.is_empty() {
+        rows.push(rest.to_string());
+    }
+    rows
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::text::wrap_words;
+    use crate::text::{font_size, wrap_line};
+
+    /// Wraps as if every character was one unit wide.
+    fn wrap_monospace(line: &str, length: usize) -> Vec<String> {
+        let positions: Vec<f32> = (0..=line.chars().count()).map(|i| i as f32).collect();
+        wrap_line(line, &positions, length as f32)
+    }
 
     #[test]
-    fn wrap_words_test() {
+    fn wrap_line_test() {
+        assert_eq!(vec!["Hi", "there"], wrap_monospace("Hi there", 5));
+        assert_eq!(
+            vec![
+                "We gotta burn the",
+                "rain forest, dump",
+                "toxic waste, pollute",
+                "the air, and rip up",
+                "the OZONE!"
+            ],
+            wrap_monospace(
+                "We gotta burn the rain forest, dump toxic waste, pollute the air, and rip up the OZONE!",
+                20
+            )
+        );
+
+        // long words are broken up, and indentation is kept
         assert_eq!(
-            "Hi \nthere ".to_string(),
-            wrap_words("Hi there".to_string(), 4)
+            vec!["    vec3", "colour_of", "_the_sky"],
+            wrap_monospace("    vec3 colour_of_the_sky", 9)
         );
-        assert_eq!("We gotta burn the \nrain forest, dump \ntoxic waste, \npollute the air, \nand rip up the \nOZONE! ".to_string(), wrap_words("We gotta burn the rain forest, dump toxic waste, pollute the air, and rip up the OZONE!".to_string(), 20));
+        assert_eq!(vec![""], wrap_monospace("", 9));
+        // always progress, even if nothing fits
+        assert_eq!(vec!["a", "b"], wrap_monospace("ab", 0));
+        assert_eq!(vec!["é", "ü", "ß"], wrap_monospace("éüß", 1));
+        // characters of different widths
+        assert_eq!(
+            vec!["ab", "c"],
+            wrap_line("abc", &[0.0, 1.0, 1.5, 3.0], 2.0)
+        );
+    }
+
+    #[test]
+    fn font_size_test() {
+        assert_eq!(20.0, font_size(720));
+        assert_eq!(14.0, font_size(100));
+        assert_eq!(32.0, font_size(4320));
     }
 }
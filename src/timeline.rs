@@ -0,0 +1,636 @@
+//! Keyframe animation of uniforms. Each uniform gets a track of keyframes, and the values in between are interpolated with the curve of the keyframe starting the segment.
+
+use std::{collections::HashMap, f32::consts::PI, mem::discriminant};
+
+use nalgebra::Matrix4;
+
+use crate::command::UniformValue;
+
+/// Interpolation curve from a keyframe to the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    /// Keep the value until the next keyframe
+    Step,
+    Linear,
+    /// Catmull-Rom spline through the surrounding keyframes
+    Cubic,
+    Ease(Easing, EaseDirection),
+}
+
+/// The standard easing functions, as found on easings.net.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Sine,
+    Quad,
+    Cubic,
+    Quart,
+    Quint,
+    Expo,
+    Circ,
+    Back,
+    Elastic,
+    Bounce,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EaseDirection {
+    In,
+    Out,
+    InOut,
+}
+
+const EASINGS: [(&str, Easing); 10] = [
+    ("sine", Easing::Sine),
+    ("quad", Easing::Quad),
+    ("cubic", Easing::Cubic),
+    ("quart", Easing::Quart),
+    ("quint", Easing::Quint),
+    ("expo", Easing::Expo),
+    ("circ", Easing::Circ),
+    ("back", Easing::Back),
+    ("elastic", Easing::Elastic),
+    ("bounce", Easing::Bounce),
+];
+
+impl Curve {
+    /// Parses curve names like "linear", "cubic" or "ease-in-out-sine".
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let ease = |direction: EaseDirection, easing: &str| {
+            EASINGS
+                .iter()
+                .find(|(easing_name, _)| *easing_name == easing)
+                .map(|(_, easing)| Curve::Ease(*easing, direction))
+        };
+
+        let curve = match name {
+            "step" => Some(Curve::Step),
+            "linear" => Some(Curve::Linear),
+            "cubic" => Some(Curve::Cubic),
+            _ => {
+                if let Some(easing) = name.strip_prefix("ease-in-out-") {
+                    ease(EaseDirection::InOut, easing)
+                } else if let Some(easing) = name.strip_prefix("ease-in-") {
+                    ease(EaseDirection::In, easing)
+                } else if let Some(easing) = name.strip_prefix("ease-out-") {
+                    ease(EaseDirection::Out, easing)
+                } else {
+                    None
+                }
+            }
+        };
+
+        curve.ok_or_else(|| {
+            format!(
+                "Unknown curve {}. Available curves: step, linear, cubic, and ease-in-, ease-out- or ease-in-out- followed by one of {}",
+                name,
+                EASINGS
+                    .iter()
+                    .map(|(easing_name, _)| *easing_name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+    }
+}
+
+impl Easing {
+    /// Eased progress for progress `x` between 0 and 1.
+    fn apply(self, direction: EaseDirection, x: f32) -> f32 {
+        // the out and in-out variants are all derived from the in variant
+        match direction {
+            EaseDirection::In => self.ease_in(x),
+            EaseDirection::Out => 1.0 - self.ease_in(1.0 - x),
+            EaseDirection::InOut if x < 0.5 => self.ease_in(2.0 * x) / 2.0,
+            EaseDirection::InOut => 1.0 - self.ease_in(2.0 - 2.0 * x) / 2.0,
+        }
+    }
+
+    fn ease_in(self, x: f32) -> f32 {
+        match self {
+            Easing::Sine => 1.0 - (x * PI / 2.0).cos(),
+            Easing::Quad => x.powi(2),
+            Easing::Cubic => x.powi(3),
+            Easing::Quart => x.powi(4),
+            Easing::Quint => x.powi(5),
+            Easing::Expo if x <= 0.0 => 0.0,
+            Easing::Expo => 2.0f32.powf(10.0 * x - 10.0),
+            Easing::Circ => 1.0 - (1.0 - x.powi(2)).max(0.0).sqrt(),
+            Easing::Back => {
+                let overshoot = 1.70158;
+                (overshoot + 1.0) * x.powi(3) - overshoot * x.powi(2)
+            }
+            Easing::Elastic if x <= 0.0 || x >= 1.0 => x.clamp(0.0, 1.0),
+            Easing::Elastic => {
+                -(2.0f32.powf(10.0 * x - 10.0)) * ((10.0 * x - 10.75) * (2.0 * PI / 3.0)).sin()
+            }
+            Easing::Bounce => 1.0 - bounce_out(1.0 - x),
+        }
+    }
+}
+
+fn bounce_out(x: f32) -> f32 {
+    let (n, d) = (7.5625, 2.75);
+    if x < 1.0 / d {
+        n * x * x
+    } else if x < 2.0 / d {
+        let x = x - 1.5 / d;
+        n * x * x + 0.75
+    } else if x < 2.5 / d {
+        let x = x - 2.25 / d;
+        n * x * x + 0.9375
+    } else {
+        let x = x - 2.625 / d;
+        n * x * x + 0.984375
+    }
+}
+
+#[derive(Debug)]
+struct Keyframe {
+    time: f32,
+    value: UniformValue,
+    curve: Curve,
+}
+
+/// Keyframes for a single uniform, sorted by time.
+#[derive(Debug, Default)]
+struct Track {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+    fn sample(&self, time: f32) -> Option<UniformValue> {
+        let keyframes = &self.keyframes;
+        let first = keyframes.first()?;
+        let last = keyframes.last()?;
+        if time <= first.time {
+            return Some(first.value.clone());
+        }
+        if time >= last.time {
+            return Some(last.value.clone());
+        }
+
+        // index of the keyframe starting the segment we are in
+        let i = keyframes.partition_point(|keyframe| keyframe.time <= time) - 1;
+        let (from, to) = (&keyframes[i], &keyframes[i + 1]);
+        let progress = (time - from.time) / (to.time - from.time);
+
+        let from_components = components(&from.value);
+        let to_components = components(&to.value);
+        let interpolated: Vec<f32> = match from.curve {
+            Curve::Step => from_components,
+            Curve::Linear => lerp(&from_components, &to_components, progress),
+            Curve::Ease(easing, direction) => lerp(
+                &from_components,
+                &to_components,
+                easing.apply(direction, progress),
+            ),
+            Curve::Cubic => {
+                // the end points are repeated where there are no keyframes to lean on
+                let before = components(&keyframes[i.saturating_sub(1)].value);
+                let after = components(&keyframes[(i + 2).min(keyframes.len() - 1)].value);
+                (0..from_components.len())
+                    .map(|component| {
+                        catmull_rom(
+                            before[component],
+                            from_components[component],
+                            to_components[component],
+                            after[component],
+                            progress,
+                        )
+                    })
+                    .collect()
+            }
+        };
+
+        Some(from_components_like(&from.value, &interpolated))
+    }
+
+    fn end(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+}
+
+/// Keyframe tracks for uniforms, with a playhead that can be played, paused, scrubbed and looped.
+#[derive(Debug)]
+pub struct Timeline {
+    tracks: HashMap<String, Track>,
+    position: f32,
+    playing: bool,
+    looping: bool,
+    speed: f32,
+    // time given to the last update, to know how far to move the playhead
+    last_update: Option<f32>,
+    // whether the uniforms should be sampled again even if the playhead didn't move
+    dirty: bool,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            tracks: HashMap::new(),
+            position: 0.0,
+            playing: false,
+            looping: false,
+            speed: 1.0,
+            last_update: None,
+            dirty: false,
+        }
+    }
+}
+
+impl Timeline {
+    /// Adds a keyframe for the uniform, replacing any keyframe at the same time. All keyframes of a uniform must have the same type.
+    pub fn add_keyframe(
+        &mut self,
+        name: String,
+        time: f32,
+        value: UniformValue,
+        curve: Curve,
+    ) -> Result<(), String> {
+        if !time.is_finite() || time < 0.0 {
+            return Err("Keyframe time should be a positive number of seconds".to_string());
+        }
+        if let UniformValue::RgbaTexture2D(_) = value {
+            return Err("Textures can't be animated".to_string());
+        }
+
+        let track = self.tracks.entry(name.clone()).or_default();
+        if let Some(keyframe) = track.keyframes.first()
+            && discriminant(&keyframe.value) != discriminant(&value)
+        {
+            return Err(format!(
+                "All keyframes of {} should have the same type",
+                name
+            ));
+        }
+
+        track.keyframes.retain(|keyframe| keyframe.time != time);
+        let index = track
+            .keyframes
+            .partition_point(|keyframe| keyframe.time < time);
+        track
+            .keyframes
+            .insert(index, Keyframe { time, value, curve });
+
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Removes all keyframes of the uniform. Returns false if it had none.
+    pub fn clear_keyframes(&mut self, name: &str) -> bool {
+        if self.tracks.remove(name).is_none() {
+            return false;
+        }
+        // the timeline may have become shorter than the playhead
+        self.wrap_position();
+        self.dirty = true;
+        true
+    }
+
+    pub fn play(&mut self) {
+        // restart from the beginning if we are at the end
+        if !self.looping && self.speed > 0.0 && self.position >= self.duration() {
+            self.position = 0.0;
+        }
+        self.playing = true;
+        self.reset_clock();
+        self.dirty = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Whether something changed that should be sampled, even if the playhead isn't moving.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Moves the playhead to the given time, in seconds.
+    pub fn seek(&mut self, position: f32) {
+        self.position = position;
+        self.wrap_position();
+        self.reset_clock();
+        self.dirty = true;
+    }
+
+    /// Forgets the time of the last update, so the next one doesn't move the playhead. For when the clock jumps instead of
+    /// running, e.g. when it is set or an export takes it over.
+    pub fn reset_clock(&mut self) {
+        self.last_update = None;
+    }
+
+    pub fn position(&self) -> f32 {
+        self.position
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Sets the playback speed. 1.0 is normal speed, while negative numbers play backwards.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Time of the last keyframe in any track.
+    pub fn duration(&self) -> f32 {
+        self.tracks.values().map(Track::end).fold(0.0, f32::max)
+    }
+
+    /// Moves the playhead forward to the given time, in seconds. Gives the sampled uniforms if they should be sent to the renderer.
+    pub fn update(&mut self, time: f32) -> Option<Vec<(String, UniformValue)>> {
+        let delta = time - self.last_update.unwrap_or(time);
+        self.last_update = Some(time);
+
+        if self.playing {
+            self.position += delta * self.speed;
+            self.wrap_position();
+        } else if !self.dirty {
+            return None;
+        }
+
+        self.dirty = false;
+        Some(self.sample())
+    }
+
+    /// Values of all animated uniforms at the current position.
+    pub fn sample(&self) -> Vec<(String, UniformValue)> {
+        self.tracks
+            .iter()
+            .filter_map(|(name, track)| Some((name.clone(), track.sample(self.position)?)))
+            .collect()
+    }
+
+    /// Loops or clamps the playhead to the timeline. Stops playing when reaching the end without looping.
+    fn wrap_position(&mut self) {
+        let duration = self.duration();
+        if self.looping && duration > 0.0 {
+            self.position = self.position.rem_euclid(duration);
+        } else if self.position <= 0.0 || self.position >= duration {
+            // only stop when moving past the end in the direction we play
+            if (self.position >= duration && self.speed > 0.0)
+                || (self.position <= 0.0 && self.speed < 0.0)
+            {
+                self.playing = false;
+            }
+            self.position = self.position.clamp(0.0, duration);
+        }
+    }
+}
+
+/// The animatable parts of a uniform value, as a flat list of numbers.
+fn components(value: &UniformValue) -> Vec<f32> {
+    match value {
+        UniformValue::Float(num) => vec![*num],
+        UniformValue::Vector3(x, y, z) => vec![*x, *y, *z],
+        UniformValue::Matrix(matrix) => matrix.as_slice().to_vec(),
+        UniformValue::RgbaTexture2D(_) => unreachable!("textures are never added as keyframes"),
+    }
+}
+
+/// Reverse of [components], creating a value of the same type as `like`.
+fn from_components_like(like: &UniformValue, components: &[f32]) -> UniformValue {
+    match like {
+        UniformValue::Float(_) => UniformValue::Float(components[0]),
+        UniformValue::Vector3(..) => {
+            UniformValue::Vector3(components[0], components[1], components[2])
+        }
+        UniformValue::Matrix(_) => UniformValue::Matrix(Matrix4::from_column_slice(components)),
+        UniformValue::RgbaTexture2D(_) => unreachable!("textures are never added as keyframes"),
+    }
+}
+
+fn lerp(from: &[f32], to: &[f32], progress: f32) -> Vec<f32> {
+    from.iter()
+        .zip(to)
+        .map(|(from, to)| from + (to - from) * progress)
+        .collect()
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    0.5 * ((2.0 * p1)
+        + (-p0 + p2) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+        + (-p0 + 3.0 * p1 - 3.0 * p2 + p3) * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::command::UniformValue;
+
+    use super::{Curve, EASINGS, EaseDirection, Easing, Timeline};
+
+    fn sample_float(timeline: &Timeline, name: &str) -> f32 {
+        match timeline
+            .sample()
+            .into_iter()
+            .find(|(uniform, _)| uniform == name)
+        {
+            Some((_, UniformValue::Float(num))) => num,
+            other => panic!("Expected a float, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn curve_parse_test() {
+        assert_eq!(Ok(Curve::Linear), Curve::parse("linear"));
+        assert_eq!(
+            Ok(Curve::Ease(Easing::Sine, EaseDirection::InOut)),
+            Curve::parse("ease-in-out-sine")
+        );
+        assert_eq!(
+            Ok(Curve::Ease(Easing::Bounce, EaseDirection::Out)),
+            Curve::parse("ease-out-bounce")
+        );
+        assert!(Curve::parse("ease-sideways-sine").is_err());
+        assert!(Curve::parse("wobbly").is_err());
+    }
+
+    #[test]
+    fn easing_end_points_test() {
+        for (_, easing) in EASINGS {
+            for direction in [EaseDirection::In, EaseDirection::Out, EaseDirection::InOut] {
+                assert_relative_eq!(0.0, easing.apply(direction, 0.0), epsilon = 0.001);
+                assert_relative_eq!(1.0, easing.apply(direction, 1.0), epsilon = 0.001);
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation_test() {
+        let mut timeline = Timeline::default();
+        timeline
+            .add_keyframe(
+                "x".to_string(),
+                0.0,
+                UniformValue::Float(0.0),
+                Curve::Linear,
+            )
+            .unwrap();
+        timeline
+            .add_keyframe("x".to_string(), 2.0, UniformValue::Float(10.0), Curve::Step)
+            .unwrap();
+        timeline
+            .add_keyframe(
+                "x".to_string(),
+                4.0,
+                UniformValue::Float(20.0),
+                Curve::Linear,
+            )
+            .unwrap();
+
+        timeline.seek(1.0);
+        assert_relative_eq!(5.0, sample_float(&timeline, "x"));
+        timeline.seek(3.0);
+        assert_relative_eq!(10.0, sample_float(&timeline, "x"));
+        // held at the ends
+        timeline.seek(100.0);
+        assert_relative_eq!(20.0, sample_float(&timeline, "x"));
+
+        // cubic curves pass through the keyframes
+        timeline
+            .add_keyframe("y".to_string(), 0.0, UniformValue::Float(1.0), Curve::Cubic)
+            .unwrap();
+        timeline
+            .add_keyframe("y".to_string(), 1.0, UniformValue::Float(3.0), Curve::Cubic)
+            .unwrap();
+        timeline.seek(1.0);
+        assert_relative_eq!(3.0, sample_float(&timeline, "y"));
+        timeline.seek(0.5);
+        assert_relative_eq!(2.0, sample_float(&timeline, "y"));
+    }
+
+    #[test]
+    fn vector_interpolation_test() {
+        let mut timeline = Timeline::default();
+        timeline
+            .add_keyframe(
+                "color".to_string(),
+                0.0,
+                UniformValue::Vector3(0.0, 0.0, 0.0),
+                Curve::Linear,
+            )
+            .unwrap();
+        timeline
+            .add_keyframe(
+                "color".to_string(),
+                1.0,
+                UniformValue::Vector3(1.0, 2.0, 4.0),
+                Curve::Linear,
+            )
+            .unwrap();
+
+        timeline.seek(0.5);
+        assert_eq!(
+            vec![("color".to_string(), UniformValue::Vector3(0.5, 1.0, 2.0))],
+            timeline.sample()
+        );
+
+        // types can't be mixed in a track
+        assert!(
+            timeline
+                .add_keyframe(
+                    "color".to_string(),
+                    2.0,
+                    UniformValue::Float(1.0),
+                    Curve::Linear
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn playback_test() {
+        let mut timeline = Timeline::default();
+        timeline
+            .add_keyframe(
+                "x".to_string(),
+                0.0,
+                UniformValue::Float(0.0),
+                Curve::Linear,
+            )
+            .unwrap();
+        timeline
+            .add_keyframe(
+                "x".to_string(),
+                4.0,
+                UniformValue::Float(4.0),
+                Curve::Linear,
+            )
+            .unwrap();
+
+        // paused timelines only sample when something changed
+        timeline.update(0.0);
+        assert!(timeline.update(1.0).is_none());
+
+        // playing starts from the next update, however long the timeline was paused
+        timeline.set_speed(2.0);
+        timeline.play();
+        timeline.update(1.0);
+        assert_relative_eq!(0.0, timeline.position());
+        timeline.update(2.0);
+        assert_relative_eq!(2.0, timeline.position());
+
+        // jumps of the clock don't move the playhead
+        timeline.reset_clock();
+        timeline.update(-5.0);
+        assert_relative_eq!(2.0, timeline.position());
+        timeline.update(-4.5);
+        assert_relative_eq!(3.0, timeline.position());
+
+        // stops at the end when not looping
+        timeline.update(10.0);
+        assert_relative_eq!(4.0, timeline.position());
+        assert!(!timeline.is_playing());
+
+        timeline.set_looping(true);
+        timeline.play();
+        timeline.update(12.0);
+        timeline.update(12.5);
+        assert_relative_eq!(1.0, timeline.position());
+    }
+
+    #[test]
+    fn clear_keyframes_test() {
+        let mut timeline = Timeline::default();
+        for (name, end) in [("x", 2.0), ("y", 8.0)] {
+            timeline
+                .add_keyframe(
+                    name.to_string(),
+                    0.0,
+                    UniformValue::Float(0.0),
+                    Curve::Linear,
+                )
+                .unwrap();
+            timeline
+                .add_keyframe(
+                    name.to_string(),
+                    end,
+                    UniformValue::Float(end),
+                    Curve::Linear,
+                )
+                .unwrap();
+        }
+        timeline.seek(6.0);
+        timeline.update(0.0);
+
+        // a paused timeline is sampled again, with the playhead within what is left
+        assert!(timeline.clear_keyframes("y"));
+        assert!(timeline.is_dirty());
+        assert_relative_eq!(2.0, timeline.position());
+        assert_eq!(
+            Some(vec![("x".to_string(), UniformValue::Float(2.0))]),
+            timeline.update(1.0)
+        );
+
+        assert!(!timeline.clear_keyframes("y"));
+        assert!(!timeline.is_dirty());
+    }
+}
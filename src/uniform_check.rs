@@ -0,0 +1,157 @@
+//! Checks of uniform values against the uniforms the shader declares, to catch misspelled names and values of the wrong type.
+
+use std::{collections::BTreeMap, mem};
+
+/// Active uniforms of a compiled shader, with their GLSL types, e.g, "vec3".
+pub type DeclaredUniforms = BTreeMap<String, String>;
+
+/// Names further from the given one than this many edits are not suggested.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UniformProblem {
+    /// Not declared by the shader, or removed by the compiler because it does not affect the output. With the declared names
+    /// it is most likely a typo of
+    Unknown(Vec<String>),
+    /// Declared with the first type, but given a value of the second
+    WrongType(String, String),
+}
+
+impl UniformProblem {
+    /// User friendly description of the problem with the named uniform.
+    pub fn message(&self, name: &str) -> String {
+        match self {
+            UniformProblem::Unknown(suggestions) if suggestions.is_empty() => {
+                format!("Uniform {} is not used by the shader", name)
+            }
+            UniformProblem::Unknown(suggestions) => format!(
+                "Uniform {} is not used by the shader. Did you mean {}?",
+                name,
+                suggestions.join(" or ")
+            ),
+            UniformProblem::WrongType(declared, given) => format!(
+                "Uniform {} is a {} in the shader, but was given a {}",
+                name, declared, given
+            ),
+        }
+    }
+}
+
+/// Compares a uniform value of the given GLSL type with what the shader declares. Gives None if it fits.
+pub fn check_uniform(
+    name: &str,
+    glsl_type: &str,
+    declared: &DeclaredUniforms,
+) -> Option<UniformProblem> {
+    match declared.get(name) {
+        Some(declared_type) if declared_type == glsl_type => None,
+        Some(declared_type) => Some(UniformProblem::WrongType(
+            declared_type.clone(),
+            glsl_type.to_string(),
+        )),
+        None => Some(UniformProblem::Unknown(similar_names(name, declared))),
+    }
+}
+
+/// Declared names that are the fewest typos away from the given one. Short names need to be closer to count.
+fn similar_names(name: &str, declared: &DeclaredUniforms) -> Vec<String> {
+    let max_distance = name
+        .chars()
+        .count()
+        .div_ceil(3)
+        .clamp(1, MAX_SUGGESTION_DISTANCE);
+    let mut similar: Vec<(usize, &String)> = declared
+        .keys()
+        .map(|declared_name| (edit_distance(name, declared_name), declared_name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    similar.sort();
+    // a closer name is much more likely to be the one meant
+    let closest = similar.first().map_or(0, |(distance, _)| *distance);
+    similar
+        .into_iter()
+        .take_while(|(distance, _)| *distance == closest)
+        .map(|(_, declared_name)| declared_name.clone())
+        .collect()
+}
+
+/// Edit distance counting swapped neighbouring characters as one edit, as that is a common typo. Ignores case, as a wrong
+/// case is a typo like any other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+
+    // distances from the first i - 1 and i characters of a to each prefix of b
+    let mut prev_row: Vec<usize> = Vec::new();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut next_row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = row[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            next_row[j] = substitution.min(row[j] + 1).min(next_row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                next_row[j] = next_row[j].min(prev_row[j - 2] + 1);
+            }
+        }
+        prev_row = mem::replace(&mut row, next_row);
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeclaredUniforms, UniformProblem, check_uniform, edit_distance};
+
+    #[test]
+    fn check_uniform_test() {
+        let declared: DeclaredUniforms = [
+            ("time", "float"),
+            ("color", "vec3"),
+            ("colour_bias", "float"),
+            ("tex", "sampler2D"),
+        ]
+        .iter()
+        .map(|(name, ty)| (name.to_string(), ty.to_string()))
+        .collect();
+
+        assert_eq!(None, check_uniform("time", "float", &declared));
+        assert_eq!(
+            Some(UniformProblem::WrongType(
+                "vec3".to_string(),
+                "float".to_string()
+            )),
+            check_uniform("color", "float", &declared)
+        );
+        assert_eq!(
+            Some(UniformProblem::Unknown(vec!["time".to_string()])),
+            check_uniform("tiem", "float", &declared)
+        );
+        assert_eq!(
+            Some(UniformProblem::Unknown(vec!["color".to_string()])),
+            check_uniform("Colour", "vec3", &declared)
+        );
+        assert_eq!(
+            Some(UniformProblem::Unknown(vec![])),
+            check_uniform("resolution", "vec3", &declared)
+        );
+
+        assert_eq!(
+            "Uniform tiem is not used by the shader. Did you mean time?",
+            UniformProblem::Unknown(vec!["time".to_string()]).message("tiem")
+        );
+        assert_eq!(
+            "Uniform color is a vec3 in the shader, but was given a float",
+            UniformProblem::WrongType("vec3".to_string(), "float".to_string()).message("color")
+        );
+    }
+
+    #[test]
+    fn edit_distance_test() {
+        assert_eq!(0, edit_distance("time", "time"));
+        assert_eq!(1, edit_distance("time", "tim"));
+        assert_eq!(1, edit_distance("time", "tiem"));
+        assert_eq!(2, edit_distance("tiem", "tex"));
+        assert_eq!(1, edit_distance("Color", "colour"));
+        assert_eq!(4, edit_distance("", "time"));
+    }
+}